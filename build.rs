@@ -0,0 +1,27 @@
+use std::{env, process::Command};
+
+/// Exposes the git commit and rustc version to `github_exporter_build_info`,
+/// falling back to `unknown` when they can't be found, e.g. when building
+/// from a source archive.
+fn main() {
+    let git_commit = output("git", &["rev-parse", "--short", "HEAD"]);
+    let rustc = output(
+        &env::var("RUSTC").unwrap_or_else(|_| "rustc".into()),
+        &["--version"],
+    );
+
+    println!("cargo:rustc-env=GIT_COMMIT={}", git_commit);
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+}
+
+fn output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|stdout| stdout.trim().to_string())
+        .unwrap_or_else(|| "unknown".into())
+}
@@ -0,0 +1,64 @@
+use crate::{
+    github,
+    types::{Organisation, Repository},
+};
+use serde_json::Value;
+
+/// What `--check` verifies.
+pub struct Targets<'a> {
+    pub repos: &'a [Repository],
+    pub orgs: &'a [Organisation],
+    pub users: &'a [String],
+    /// GitHub Enterprise Server has no billing endpoints.
+    pub billing: bool,
+}
+
+/// Verifies that the token works and that every configured repository and
+/// billing endpoint can be read, printing one line per check. Answers whether
+/// all of them passed.
+pub async fn run(targets: Targets<'_>) -> bool {
+    let mut checks = vec![("authentication".to_string(), "rate_limit".to_string())];
+
+    for repo in targets.repos {
+        checks.push((
+            format!("repo {}", repo),
+            format!("repos/{}/{}", repo.owner, repo.name),
+        ));
+    }
+
+    if targets.billing {
+        for org in targets.orgs {
+            checks.push((
+                format!("org `{}` billing", org),
+                format!("orgs/{}/settings/billing/actions", org),
+            ));
+        }
+
+        for user in targets.users {
+            checks.push((
+                format!("user `{}` billing", user),
+                format!("users/{}/settings/billing/actions", user),
+            ));
+        }
+    }
+
+    let mut failed = 0;
+
+    for (name, path) in &checks {
+        match github::get::<Value, _>("check", path, None::<&()>).await {
+            Ok(_) => println!("ok      {}", name),
+            Err(err) => {
+                failed += 1;
+                println!("FAILED  {}: {}", name, err);
+            }
+        }
+    }
+
+    println!(
+        "{} of {} checks passed",
+        checks.len() - failed,
+        checks.len()
+    );
+
+    failed == 0
+}
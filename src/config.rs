@@ -0,0 +1,93 @@
+use crate::{
+    repositories::Repositories,
+    types::{Organisation, Organisations, Repository},
+};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{fs, time};
+use tracing::{error, info};
+
+/// Settings read from `--config-file`. Anything also given through a flag or
+/// an environment variable is overridden by it.
+#[serde_as]
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub repos: Vec<Repository>,
+    pub orgs: Vec<Organisation>,
+    /// Poll interval from GitHub API (in seconds).
+    pub poll_interval: Option<u64>,
+    /// Interval when to refresh workflows cache (in seconds).
+    pub workflows_refresh: Option<u64>,
+    pub token: Option<String>,
+}
+
+impl Config {
+    pub async fn read(path: &Path) -> Result<Self> {
+        Ok(serde_yaml::from_str(&fs::read_to_string(path).await?)?)
+    }
+}
+
+/// Lists hot-reloaded from the config file, `None` for those coming from the
+/// command line, which wins over the file.
+#[derive(Debug, Clone)]
+pub struct Reload {
+    pub repos: Option<Repositories>,
+    pub orgs: Option<Organisations>,
+}
+
+/// Re-reads the config file on every `interval`, applying changes to the
+/// repository and organisation lists. Other settings only apply on restart.
+pub async fn watch(path: PathBuf, interval: Duration, mut config: Config, reload: Reload) {
+    loop {
+        time::sleep(interval).await;
+
+        let new_config = match Config::read(&path).await {
+            Ok(new_config) => new_config,
+            Err(err) => {
+                error!("failed to read config file {}: {}", path.display(), err);
+                continue;
+            }
+        };
+
+        if new_config == config {
+            continue;
+        }
+
+        if let Some(repositories) = &reload.repos {
+            let previous = config.repos.iter().collect::<HashSet<_>>();
+            let current = new_config.repos.iter().collect::<HashSet<_>>();
+
+            for repo in current.difference(&previous) {
+                if repositories.insert((*repo).clone()).await {
+                    info!("added repo {} from config file", repo);
+                }
+            }
+
+            for repo in previous.difference(&current) {
+                info!("removed repo {} from config file", repo);
+                repositories.remove(repo).await;
+            }
+        }
+
+        if let Some(orgs) = &reload.orgs {
+            if new_config.orgs != config.orgs {
+                info!(
+                    "reloaded organisations from config file: {:?}",
+                    new_config.orgs
+                );
+                orgs.store(Arc::new(new_config.orgs.clone()));
+            }
+        }
+
+        config = new_config;
+    }
+}
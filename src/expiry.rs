@@ -0,0 +1,77 @@
+use lazy_static::lazy_static;
+use prometheus::core::{MetricVec, MetricVecBuilder};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::time;
+use tracing::info;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Metric vectors whose series can be expired.
+pub trait Expire: Sync {
+    fn expire(&self, labels: &[&str]);
+}
+
+impl<T: MetricVecBuilder> Expire for MetricVec<T> {
+    fn expire(&self, labels: &[&str]) {
+        let _ = self.remove_label_values(labels);
+    }
+}
+
+/// Series not set for longer than `max_age` get removed by [`watch`].
+pub fn enable(max_age: Duration) {
+    *MAX_AGE.write().unwrap() = Some(max_age);
+}
+
+/// Records that the series of `vec` for `labels` was just set from fresh data.
+/// Does nothing unless expiry is enabled.
+pub fn touch(vec: &'static dyn Expire, labels: &[&str]) {
+    if MAX_AGE.read().unwrap().is_none() {
+        return;
+    }
+
+    let key = (
+        vec as *const dyn Expire as *const () as usize,
+        labels.iter().map(|l| l.to_string()).collect(),
+    );
+
+    TOUCHED.lock().unwrap().insert(key, (vec, Instant::now()));
+}
+
+/// Removes series whose data is older than the max age, so that while GitHub
+/// can't be reached scrapes show the data as missing rather than stale.
+pub async fn watch() {
+    let max_age = match *MAX_AGE.read().unwrap() {
+        Some(max_age) => max_age,
+        None => return,
+    };
+
+    loop {
+        time::sleep(CHECK_INTERVAL.min(max_age)).await;
+
+        TOUCHED.lock().unwrap().retain(|(_, labels), (vec, at)| {
+            if at.elapsed() <= max_age {
+                return true;
+            }
+
+            info!(
+                "expiring series {:?}, not updated for {:?}",
+                labels, max_age
+            );
+            vec.expire(&labels.iter().map(String::as_str).collect::<Vec<_>>());
+
+            false
+        });
+    }
+}
+
+type Series = (usize, Vec<String>);
+
+lazy_static! {
+    static ref MAX_AGE: RwLock<Option<Duration>> = RwLock::new(None);
+    static ref TOUCHED: Mutex<HashMap<Series, (&'static dyn Expire, Instant)>> =
+        Mutex::new(HashMap::new());
+}
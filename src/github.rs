@@ -0,0 +1,854 @@
+use crate::types::ApiHeader;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hyper::{
+    header::{AUTHORIZATION, RETRY_AFTER},
+    Method, StatusCode,
+};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use lazy_static::lazy_static;
+use octocrab::{FromResponse, Octocrab, OctocrabBuilder, Page};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge,
+};
+use reqwest::Response;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{
+    fs,
+    time::{self, Instant},
+};
+use tracing::{error, info};
+
+/// Everything needed to (re)build the global Octocrab instance, except the
+/// token which may change at runtime.
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    pub base_url: Option<String>,
+    pub headers: Vec<ApiHeader>,
+}
+
+impl ClientConfig {
+    /// Replaces the global Octocrab instance. Tasks fetch it through
+    /// `octocrab::instance()` on every poll, so they pick up the new one on
+    /// their next request.
+    pub fn initialise(&self, token: String) -> Result<()> {
+        octocrab::initialise(self.builder(token)?)?;
+
+        Ok(())
+    }
+
+    /// Builds a separate instance for requests about `owner`, e.g. for
+    /// organisations behind SSO the main token isn't authorized for.
+    pub fn initialise_owner(&self, owner: &str, token: String) -> Result<()> {
+        let octocrab = self.builder(token)?.build()?;
+
+        OWNER_CLIENTS
+            .write()
+            .unwrap()
+            .insert(owner.to_lowercase(), Arc::new(octocrab));
+
+        Ok(())
+    }
+
+    fn builder(&self, token: String) -> Result<OctocrabBuilder> {
+        let mut builder = Octocrab::builder().personal_token(token);
+
+        if let Some(s) = &self.base_url {
+            builder = builder.base_url(s.as_str())?;
+        }
+
+        for header in &self.headers {
+            builder = builder.add_header(header.name.clone(), header.value.clone());
+        }
+
+        Ok(builder)
+    }
+}
+
+/// The instance for requests to `path`: the owner's own, if it was given a
+/// token, or the global one.
+fn client(path: &str) -> Arc<Octocrab> {
+    owner_client(path_owner(path))
+}
+
+/// The instance for requests about `owner`, falling back to the global one.
+fn owner_client(owner: Option<&str>) -> Arc<Octocrab> {
+    owner
+        .and_then(|owner| {
+            OWNER_CLIENTS
+                .read()
+                .unwrap()
+                .get(&owner.to_lowercase())
+                .cloned()
+        })
+        .unwrap_or_else(octocrab::instance)
+}
+
+/// Owner a request is about, from paths like `repos/{owner}/{repo}/...`,
+/// `orgs/{org}/...` or `users/{user}/...`.
+fn path_owner(path: &str) -> Option<&str> {
+    let mut segments = path.split('/').filter(|s| !s.is_empty());
+
+    segments.find(|s| matches!(*s, "repos" | "orgs" | "users"))?;
+    segments.next()
+}
+
+pub async fn read_token_file(path: &Path) -> Result<String> {
+    Ok(fs::read_to_string(path).await?.trim().to_string())
+}
+
+/// Re-reads the token from `path` on every `interval`, rebuilding the
+/// Octocrab instance whenever it changed.
+pub async fn watch_token_file(
+    config: ClientConfig,
+    path: PathBuf,
+    mut token: String,
+    interval: Duration,
+) {
+    loop {
+        time::sleep(interval).await;
+
+        let new_token = match read_token_file(&path).await {
+            Ok(new_token) if !new_token.is_empty() => new_token,
+            Ok(_) => {
+                error!(
+                    "token file {} is empty, keeping the current token",
+                    path.display()
+                );
+                continue;
+            }
+            Err(err) => {
+                error!("failed to read token file {}: {}", path.display(), err);
+                continue;
+            }
+        };
+
+        if new_token == token {
+            continue;
+        }
+
+        match config.initialise(new_token.clone()) {
+            Ok(()) => {
+                info!("reloaded GitHub token from {}", path.display());
+                token = new_token;
+            }
+            Err(err) => error!(
+                "failed to rebuild GitHub client with reloaded token: {}",
+                err
+            ),
+        }
+    }
+}
+
+/// GitHub App installation the exporter authenticates as.
+pub struct AppInstallation {
+    pub id: u64,
+    pub installation_id: u64,
+    pub private_key: EncodingKey,
+}
+
+/// Installation tokens are valid for an hour, they are replaced this long
+/// before expiring so that no poll runs with an expired one.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+const TOKEN_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+pub async fn read_private_key(path: &Path) -> Result<EncodingKey> {
+    Ok(EncodingKey::from_rsa_pem(&fs::read(path).await?)?)
+}
+
+impl AppInstallation {
+    /// Exchanges a JWT signed with the app's private key for a token of its
+    /// installation.
+    pub async fn installation_token(&self, config: &ClientConfig) -> Result<InstallationToken> {
+        let now = Utc::now().timestamp();
+        let claims = AppClaims {
+            // Allows for some clock drift with GitHub.
+            iat: now - 60,
+            exp: now + 9 * 60,
+            iss: self.id,
+        };
+        let jwt = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.private_key)?;
+
+        let mut builder = Octocrab::builder().add_header(AUTHORIZATION, format!("Bearer {}", jwt));
+
+        if let Some(s) = &config.base_url {
+            builder = builder.base_url(s.as_str())?;
+        }
+
+        for header in &config.headers {
+            builder = builder.add_header(header.name.clone(), header.value.clone());
+        }
+
+        let octocrab = builder.build()?;
+        let endpoint = "app_installation_token";
+
+        count_request(endpoint, Method::POST);
+
+        Ok(observe(
+            endpoint,
+            octocrab
+                .post(
+                    format!("app/installations/{}/access_tokens", self.installation_id),
+                    None::<&()>,
+                )
+                .await,
+        )?)
+    }
+}
+
+/// Mints a new installation token shortly before the current one expires at
+/// `expires_at`, rebuilding the Octocrab instance with it.
+pub async fn watch_installation_token(
+    config: ClientConfig,
+    app: AppInstallation,
+    mut expires_at: DateTime<Utc>,
+) {
+    loop {
+        let refresh_in = (expires_at - Utc::now())
+            .to_std()
+            .unwrap_or_default()
+            .checked_sub(TOKEN_REFRESH_MARGIN)
+            .unwrap_or_default();
+
+        time::sleep(refresh_in).await;
+
+        let token = match app.installation_token(&config).await {
+            Ok(token) => token,
+            Err(err) => {
+                error!("failed to mint GitHub App installation token: {}", err);
+                time::sleep(TOKEN_RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+
+        match config.initialise(token.token) {
+            Ok(()) => {
+                info!("refreshed GitHub App installation token");
+                expires_at = token.expires_at;
+            }
+            Err(err) => {
+                error!(
+                    "failed to rebuild GitHub client with refreshed token: {}",
+                    err
+                );
+                time::sleep(TOKEN_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AppClaims {
+    iat: i64,
+    exp: i64,
+    iss: u64,
+}
+
+#[derive(Deserialize)]
+pub struct InstallationToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// GETs `path` from the GitHub API. `endpoint` names the kind of request in
+/// metrics, without ids that would explode cardinality.
+pub async fn get<R, P>(
+    endpoint: &'static str,
+    path: impl AsRef<str>,
+    params: Option<&P>,
+) -> Result<R>
+where
+    R: FromResponse,
+    P: Serialize + ?Sized,
+{
+    send(
+        endpoint,
+        &client(path.as_ref()),
+        path,
+        params,
+        request_timeout(),
+        |response| decode(endpoint, response),
+    )
+    .await
+}
+
+/// Like [`get`], but answers `None` when GitHub responds with one of the
+/// `missing` statuses, e.g. 404 for features an organisation doesn't have.
+pub async fn get_optional<R, P>(
+    endpoint: &'static str,
+    path: impl AsRef<str>,
+    params: Option<&P>,
+    missing: &[StatusCode],
+) -> Result<Option<R>>
+where
+    R: DeserializeOwned,
+    P: Serialize + ?Sized,
+{
+    Ok(get_or_status(endpoint, path, params, missing).await?.ok())
+}
+
+/// Like [`get_optional`], for when it matters which of the `missing`
+/// statuses GitHub responded with.
+pub async fn get_or_status<R, P>(
+    endpoint: &'static str,
+    path: impl AsRef<str>,
+    params: Option<&P>,
+    missing: &[StatusCode],
+) -> Result<std::result::Result<R, StatusCode>>
+where
+    R: DeserializeOwned,
+    P: Serialize + ?Sized,
+{
+    send(
+        endpoint,
+        &client(path.as_ref()),
+        path,
+        params,
+        request_timeout(),
+        |response| decode_or_status(endpoint, response, missing),
+    )
+    .await
+}
+
+/// Like [`get`] for statistics endpoints, answering `None` while GitHub is
+/// still computing the statistics (202), which are usually ready by the next
+/// poll, and for empty repositories (204).
+pub async fn get_stats<R, P>(
+    endpoint: &'static str,
+    path: impl AsRef<str>,
+    params: Option<&P>,
+) -> Result<Option<R>>
+where
+    R: FromResponse,
+    P: Serialize + ?Sized,
+{
+    send(
+        endpoint,
+        &client(path.as_ref()),
+        path,
+        params,
+        request_timeout(),
+        |response| decode_stats(endpoint, response),
+    )
+    .await
+}
+
+async fn decode<R: FromResponse>(endpoint: &'static str, response: Response) -> Result<R> {
+    let response = observe(endpoint, octocrab::map_github_error(response).await)?;
+
+    Ok(R::from_response(response).await?)
+}
+
+async fn decode_or_status<R: DeserializeOwned>(
+    endpoint: &'static str,
+    response: Response,
+    missing: &[StatusCode],
+) -> Result<std::result::Result<R, StatusCode>> {
+    if missing.contains(&response.status()) {
+        return Ok(Err(response.status()));
+    }
+
+    let response = observe(endpoint, octocrab::map_github_error(response).await)?;
+
+    Ok(Ok(response.json().await?))
+}
+
+async fn decode_stats<R: FromResponse>(
+    endpoint: &'static str,
+    response: Response,
+) -> Result<Option<R>> {
+    match response.status() {
+        StatusCode::ACCEPTED => {
+            API_STATS_PENDING.with_label_values(&[endpoint]).inc();
+            return Ok(None);
+        }
+        StatusCode::NO_CONTENT => return Ok(None),
+        _ => {}
+    }
+
+    Ok(Some(decode(endpoint, response).await?))
+}
+
+/// How requests that failed transiently are retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every following one.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+pub fn set_retry_policy(policy: RetryPolicy) {
+    *RETRY_POLICY.write().unwrap() = policy;
+}
+
+/// Sets how long a single request, from connecting to reading the whole
+/// response, may take before it is abandoned. Octocrab doesn't let us
+/// configure its reqwest client, so this is enforced around every request.
+pub fn set_request_timeout(timeout: Duration) {
+    *REQUEST_TIMEOUT.write().unwrap() = timeout;
+}
+
+fn request_timeout() -> Duration {
+    *REQUEST_TIMEOUT.read().unwrap()
+}
+
+/// What came of a single attempt at a request.
+enum Attempt<T> {
+    Done(Result<T>),
+    /// Failed transiently, worth retrying after the delay.
+    RetryIn(Duration),
+}
+
+/// GETs `path` with `octocrab` and reads the response with `decode`, retrying
+/// requests that didn't go through, timed out or were answered with a server
+/// error or a `Retry-After` header. Once retries run out the last response is
+/// decoded as is. The `timeout` covers each attempt as a whole, so a response
+/// stalling halfway through its body can't hold up a task either.
+async fn send<T, P, D, F>(
+    endpoint: &'static str,
+    octocrab: &Octocrab,
+    path: impl AsRef<str>,
+    params: Option<&P>,
+    timeout: Duration,
+    decode: D,
+) -> Result<T>
+where
+    P: Serialize + ?Sized,
+    D: Fn(Response) -> F,
+    F: Future<Output = Result<T>>,
+{
+    let policy = *RETRY_POLICY.read().unwrap();
+
+    let url = octocrab
+        .absolute_url(path)
+        .expect("failed to generate absolute API url");
+
+    let mut attempt = 0;
+
+    loop {
+        wait_for_secondary_rate_limit().await;
+
+        count_request(endpoint, Method::GET);
+        let retries_left = attempt < policy.max_retries;
+        let timer = API_REQUEST_DURATION
+            .with_label_values(&[endpoint])
+            .start_timer();
+        let result = time::timeout(timeout, async {
+            let response = match octocrab._get(url.clone(), params).await {
+                Ok(response) => response,
+                Err(_) if retries_left => {
+                    return Attempt::RetryIn(backoff(policy.base_delay, attempt))
+                }
+                Err(err) => return Attempt::Done(Err(err.into())),
+            };
+
+            match retry_delay(&response, policy, attempt) {
+                Some(delay) if retries_left => Attempt::RetryIn(delay),
+                _ => Attempt::Done(decode(response).await),
+            }
+        })
+        .await;
+        timer.observe_duration();
+
+        let delay = match result {
+            Ok(Attempt::Done(result)) => return result,
+            Ok(Attempt::RetryIn(delay)) => delay,
+            Err(_) if retries_left => backoff(policy.base_delay, attempt),
+            Err(elapsed) => return Err(elapsed.into()),
+        };
+
+        API_RETRIES.with_label_values(&[endpoint]).inc();
+        attempt += 1;
+        time::sleep(delay).await;
+    }
+}
+
+/// How long to wait before retrying a request GitHub answered with
+/// `response`, if it is worth retrying at all.
+fn retry_delay(response: &Response, policy: RetryPolicy, attempt: u32) -> Option<Duration> {
+    match retry_after(response) {
+        Some(delay) => {
+            if is_rate_limited(response.status()) {
+                throttle_secondary_rate_limit(delay);
+            }
+
+            Some(delay)
+        }
+        None if is_transient(response.status()) => Some(backoff(policy.base_delay, attempt)),
+        None => None,
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// GitHub asks to wait with `Retry-After`, in seconds, e.g. on secondary
+/// rate limits.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Secondary rate limits are answered with a 403 or 429 and a `Retry-After`.
+fn is_rate_limited(status: StatusCode) -> bool {
+    status == StatusCode::FORBIDDEN || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Holds back every request, from all tasks, for the `delay` GitHub asked
+/// for: polling on as usual only makes the secondary rate limit last longer.
+fn throttle_secondary_rate_limit(delay: Duration) {
+    let until = Instant::now() + delay;
+    let mut throttled_until = THROTTLED_UNTIL.write().unwrap();
+
+    if throttled_until.map_or(true, |t| t < until) {
+        *throttled_until = Some(until);
+    }
+
+    info!(
+        "hit the secondary rate limit, pausing requests for {:?}",
+        delay
+    );
+    SECONDARY_RATE_LIMITED.set(1);
+    SECONDARY_RATE_LIMIT_THROTTLED.inc();
+}
+
+/// Whether requests are held back because of the secondary rate limit.
+pub fn is_throttled() -> bool {
+    THROTTLED_UNTIL
+        .read()
+        .unwrap()
+        .map_or(false, |until| until > Instant::now())
+}
+
+/// Waits until the secondary rate limit, if any, has passed.
+async fn wait_for_secondary_rate_limit() {
+    loop {
+        let until = match *THROTTLED_UNTIL.read().unwrap() {
+            Some(until) => until,
+            None => return,
+        };
+
+        if until <= Instant::now() {
+            let mut throttled_until = THROTTLED_UNTIL.write().unwrap();
+
+            // Another request may have been throttled again meanwhile.
+            if *throttled_until == Some(until) {
+                *throttled_until = None;
+                SECONDARY_RATE_LIMITED.set(0);
+            }
+
+            return;
+        }
+
+        time::sleep_until(until).await;
+    }
+}
+
+/// `base_delay` doubled for every previous attempt, of which a random half is
+/// dropped so that tasks failing at the same time don't retry in lockstep.
+fn backoff(base_delay: Duration, attempt: u32) -> Duration {
+    let delay = base_delay * 2u32.saturating_pow(attempt);
+
+    // Every RandomState is seeded differently, which is random enough for
+    // jitter without pulling in a dependency.
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+
+    delay / 2 + (delay / 2).mul_f64(random)
+}
+
+/// Fetches the page following `page`, if any, retrying like [`get`]. Links
+/// to later pages often address repositories by id, as in
+/// `repositories/{id}/...`, so the client is picked by the `owner` the first
+/// page was requested for rather than by the link.
+pub async fn get_next_page<T: DeserializeOwned>(
+    endpoint: &'static str,
+    owner: &str,
+    page: &Page<T>,
+) -> Result<Option<Page<T>>> {
+    let next = match &page.next {
+        Some(next) => next,
+        None => return Ok(None),
+    };
+
+    let page = send(
+        endpoint,
+        &owner_client(Some(owner)),
+        next.as_str(),
+        None::<&()>,
+        request_timeout(),
+        |response| decode(endpoint, response),
+    )
+    .await?;
+
+    Ok(Some(page))
+}
+
+/// Records a request to `endpoint` about to be sent, retries included.
+fn count_request(endpoint: &'static str, method: Method) {
+    API_REQUESTS
+        .with_label_values(&[endpoint, method.as_str()])
+        .inc();
+}
+
+/// Records failures of requests to `endpoint` before handing the result back.
+pub fn observe<T>(endpoint: &'static str, result: octocrab::Result<T>) -> octocrab::Result<T> {
+    if let Err(octocrab::Error::GitHub { source, .. }) = &result {
+        if is_secondary_rate_limit(&source.message) {
+            API_SECONDARY_RATE_LIMIT
+                .with_label_values(&[endpoint])
+                .inc();
+        }
+    }
+
+    result
+}
+
+/// Secondary rate limits come back as plain 403s, only their message tells
+/// them apart from permission errors. Older API versions call them abuse
+/// detection.
+fn is_secondary_rate_limit(message: &str) -> bool {
+    let message = message.to_lowercase();
+
+    message.contains("secondary rate limit") || message.contains("abuse detection")
+}
+
+lazy_static! {
+    static ref RETRY_POLICY: RwLock<RetryPolicy> = RwLock::new(RetryPolicy::default());
+    static ref OWNER_CLIENTS: RwLock<HashMap<String, Arc<Octocrab>>> =
+        RwLock::new(HashMap::new());
+    static ref THROTTLED_UNTIL: RwLock<Option<Instant>> = RwLock::new(None);
+    static ref REQUEST_TIMEOUT: RwLock<Duration> = RwLock::new(Duration::from_secs(30));
+    pub static ref API_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "github_api_requests_total",
+        "Number of requests sent to the Github API",
+        &["endpoint", "method"]
+    )
+    .unwrap();
+    pub static ref API_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "github_api_request_duration_seconds",
+        "Time until the Github API answered a request, retries observed separately",
+        &["endpoint"]
+    )
+    .unwrap();
+    pub static ref API_STATS_PENDING: IntCounterVec = register_int_counter_vec!(
+        "github_api_stats_pending_total",
+        "Number of Github API statistics requests answered while the statistics were still being computed",
+        &["endpoint"]
+    )
+    .unwrap();
+    pub static ref API_RETRIES: IntCounterVec = register_int_counter_vec!(
+        "github_api_retries_total",
+        "Number of Github API requests retried after failing transiently",
+        &["endpoint"]
+    )
+    .unwrap();
+    pub static ref SECONDARY_RATE_LIMITED: IntGauge = register_int_gauge!(
+        "github_secondary_rate_limited",
+        "Whether requests are paused because of Github's secondary rate limit (1) or not (0)"
+    )
+    .unwrap();
+    pub static ref SECONDARY_RATE_LIMIT_THROTTLED: IntCounter = register_int_counter!(
+        "github_secondary_rate_limit_throttled_total",
+        "Number of times requests were paused because of Github's secondary rate limit"
+    )
+    .unwrap();
+    pub static ref API_SECONDARY_RATE_LIMIT: IntCounterVec = register_int_counter_vec!(
+        "github_api_secondary_rate_limit_total",
+        "Number of Github API requests rejected by secondary rate limits",
+        &["endpoint"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::ClientConfig;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server,
+    };
+    use std::{convert::Infallible, future::Future, net::TcpListener};
+
+    /// Serves `handle` on a random local port, for requests about `owner`
+    /// only so that tests running in parallel don't share a mock.
+    pub fn mock_github<H, F>(owner: &str, handle: H)
+    where
+        H: Fn(Request<Body>) -> F + Clone + Send + Sync + 'static,
+        F: Future<Output = Response<Body>> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let make_service = make_service_fn(move |_conn| {
+            let handle = handle.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let response = handle(req);
+
+                    async move { Ok::<_, Infallible>(response.await) }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+
+        ClientConfig {
+            base_url: Some(base_url),
+            headers: Vec::new(),
+        }
+        .initialise_owner(owner, "token".into())
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{testing::mock_github, *};
+    use hyper::{
+        header::{HOST, LINK},
+        Body,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn response_stalling_mid_body_times_out() {
+        mock_github("stalled", |_req| async {
+            let (mut sender, body) = Body::channel();
+
+            tokio::spawn(async move {
+                let _ = sender.send_data("{\"id\":".into()).await;
+                // Keeps the body open without ever finishing it.
+                time::sleep(Duration::from_secs(3600)).await;
+            });
+
+            hyper::Response::new(body)
+        });
+
+        let result = time::timeout(
+            Duration::from_secs(10),
+            send(
+                "test",
+                &client("repos/stalled/repo"),
+                "repos/stalled/repo",
+                None::<&()>,
+                // For this request only, the global timeout is shared by all tests.
+                Duration::from_millis(200),
+                |response| decode::<serde_json::Value>("test", response),
+            ),
+        )
+        .await
+        .expect("request hung instead of timing out");
+
+        assert!(result.unwrap_err().is::<time::error::Elapsed>());
+    }
+
+    #[tokio::test]
+    async fn follows_next_page_through_transient_errors() {
+        let next_page_requests = Arc::new(AtomicUsize::new(0));
+        let requests = next_page_requests.clone();
+
+        mock_github("paged", move |req| {
+            let requests = requests.clone();
+
+            async move {
+                let host = req.headers()[HOST].to_str().unwrap().to_string();
+
+                match req.uri().query() {
+                    None => hyper::Response::builder()
+                        .header(
+                            LINK,
+                            format!(
+                                "<http://{}/repos/paged/repo/items?page=2>; rel=\"next\"",
+                                host
+                            ),
+                        )
+                        .body(Body::from("[1, 2]"))
+                        .unwrap(),
+                    // The first request for the second page fails transiently.
+                    Some(_) if requests.fetch_add(1, Ordering::SeqCst) == 0 => {
+                        hyper::Response::builder()
+                            .status(StatusCode::SERVICE_UNAVAILABLE)
+                            .body(Body::empty())
+                            .unwrap()
+                    }
+                    Some(_) => hyper::Response::new(Body::from("[3]")),
+                }
+            }
+        });
+
+        let first = get::<Page<u32>, _>("test", "repos/paged/repo/items", None::<&()>)
+            .await
+            .unwrap();
+        assert_eq!(first.items, vec![1, 2]);
+
+        let second = get_next_page("test", "paged", &first)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.items, vec![3]);
+        assert_eq!(next_page_requests.load(Ordering::SeqCst), 2);
+
+        assert!(get_next_page("test", "paged", &second)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn follows_next_page_by_repository_id_with_the_owner_client() {
+        mock_github("by-id", |req| async move {
+            let host = req.headers()[HOST].to_str().unwrap().to_string();
+
+            if !req.headers().contains_key(AUTHORIZATION) {
+                return hyper::Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::from("{\"message\": \"Requires authentication\"}"))
+                    .unwrap();
+            }
+
+            match req.uri().path() {
+                "/repos/by-id/repo/items" => hyper::Response::builder()
+                    .header(
+                        LINK,
+                        format!(
+                            "<http://{}/repositories/42/items?page=2>; rel=\"next\"",
+                            host
+                        ),
+                    )
+                    .body(Body::from("[1, 2]"))
+                    .unwrap(),
+                _ => hyper::Response::new(Body::from("[3]")),
+            }
+        });
+
+        let first = get::<Page<u32>, _>("test", "repos/by-id/repo/items", None::<&()>)
+            .await
+            .unwrap();
+        let second = get_next_page("test", "by-id", &first)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(second.items, vec![3]);
+    }
+}
@@ -1,20 +1,129 @@
-use anyhow::Result;
+use crate::{metrics, pause, ready, shutdown::Shutdown, types::RelabelRule};
+use anyhow::{anyhow, Result};
+use arc_swap::ArcSwapOption;
+use format::Format;
 use hyper::{
-    header::CONTENT_TYPE,
+    body::Bytes,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    server::conn::Http,
     service::{make_service_fn, service_fn},
     Body, Method, Request, Response, Server, StatusCode,
 };
 use lazy_static::lazy_static;
 use prometheus::{
-    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
-    TextEncoder,
+    proto::MetricFamily, register_histogram_vec, register_int_counter_vec, HistogramVec,
+    IntCounterVec,
 };
-use std::net::SocketAddr;
-use tracing::{info, span, Instrument, Level};
+use std::{
+    fmt::Display,
+    fs,
+    net::SocketAddr,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    slice,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, UnixListener},
+    time,
+};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tracing::{error, info, span, Instrument, Level};
+
+mod format;
+pub mod tls;
+
+/// Where to listen: a TCP address, IPv4 or IPv6, or a Unix domain socket
+/// given as `unix:/path/to.sock`.
+#[derive(Debug, Clone)]
+pub enum Bind {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for Bind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("unix socket path must not be empty!".into()),
+            Some(path) => Ok(Bind::Unix(path.into())),
+            None => SocketAddr::from_str(s)
+                .map(Bind::Tcp)
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+impl Display for Bind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bind::Tcp(addr) => write!(f, "{}", addr),
+            Bind::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
 
-pub async fn listen(addr: &SocketAddr) -> Result<()> {
-    let make_service = make_service_fn(|_conn| async { Ok::<_, hyper::Error>(service_fn(handle)) });
-    let server = Server::bind(addr).serve(make_service);
+#[derive(Default)]
+pub struct Options {
+    /// When set, `/metrics` serves metrics gathered and pre-rendered in every
+    /// format on this interval instead of on every scrape.
+    pub snapshot_interval: Option<Duration>,
+    /// Path prefix all routes are served under, e.g. `/gh-exporter`.
+    pub route_prefix: String,
+    /// Bearer token required by the admin routes, which are disabled when
+    /// unset.
+    pub admin_token: Option<String>,
+    /// Applied in order to the gathered metrics before encoding them.
+    pub relabel_rules: Vec<RelabelRule>,
+    /// Serves HTTPS instead of plain HTTP when set.
+    pub tls: Option<Arc<ServerConfig>>,
+}
+
+#[derive(Default)]
+struct State {
+    snapshot: ArcSwapOption<Snapshot>,
+    route_prefix: String,
+    admin_token: Option<String>,
+    relabel_rules: Vec<RelabelRule>,
+}
+
+/// Serves until `shutdown` is requested, then stops accepting connections and
+/// lets the ones in flight finish.
+pub async fn listen(bind: &Bind, options: Options, mut shutdown: Shutdown) -> Result<()> {
+    let state = Arc::new(State {
+        route_prefix: normalize_prefix(&options.route_prefix),
+        admin_token: options.admin_token,
+        relabel_rules: options.relabel_rules,
+        ..State::default()
+    });
+
+    if let Some(interval) = options.snapshot_interval {
+        tokio::spawn(refresh_snapshot(state.clone(), interval));
+    }
+
+    let addr = match bind {
+        Bind::Tcp(addr) => addr,
+        Bind::Unix(_) if options.tls.is_some() => {
+            return Err(anyhow!("TLS is not supported on unix sockets"));
+        }
+        Bind::Unix(path) => return listen_unix(path, state, shutdown).await,
+    };
+
+    if let Some(config) = options.tls {
+        return listen_tls(addr, config, state, shutdown).await;
+    }
+
+    let make_service = make_service_fn(move |_conn| {
+        let state = state.clone();
+
+        async move { Ok::<_, hyper::Error>(service_fn(move |req| handle(req, state.clone()))) }
+    });
+    let server = Server::bind(addr)
+        .serve(make_service)
+        .with_graceful_shutdown(async move { shutdown.requested().await });
 
     info!("listening on {}", addr);
 
@@ -23,7 +132,143 @@ pub async fn listen(addr: &SocketAddr) -> Result<()> {
     Ok(())
 }
 
-async fn handle(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+/// Like [`listen_tls`], for connections to a Unix domain socket at `path`,
+/// which is removed again on shutdown.
+async fn listen_unix(path: &Path, state: Arc<State>, mut shutdown: Shutdown) -> Result<()> {
+    // A socket left behind by a previous run that didn't shut down cleanly.
+    if fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    info!("listening on unix:{}", path.display());
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.requested() => break,
+        };
+
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                error!("failed to accept connection: {}", err);
+                continue;
+            }
+        };
+
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle(req, state.clone()));
+
+            if let Err(err) = Http::new().serve_connection(stream, service).await {
+                error!("failed to serve connection on unix socket: {}", err);
+            }
+        });
+    }
+
+    fs::remove_file(path)?;
+
+    Ok(())
+}
+
+/// Hyper's server only accepts plain TCP connections, TLS ones are accepted
+/// here and each served on its own.
+async fn listen_tls(
+    addr: &SocketAddr,
+    config: Arc<ServerConfig>,
+    state: Arc<State>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let acceptor = TlsAcceptor::from(config);
+
+    info!("listening on {} with TLS", addr);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.requested() => return Ok(()),
+        };
+
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                error!("failed to accept connection: {}", err);
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("TLS handshake with {} failed: {}", peer, err);
+                    return;
+                }
+            };
+
+            let service = service_fn(move |req| handle(req, state.clone()));
+
+            if let Err(err) = Http::new().serve_connection(stream, service).await {
+                error!("failed to serve connection from {}: {}", peer, err);
+            }
+        });
+    }
+}
+
+/// Metrics gathered on the snapshot interval, rendered in every format ahead
+/// of scrapes. The families themselves are kept for filtered scrapes.
+struct Snapshot {
+    metric_families: Vec<MetricFamily>,
+    text: Bytes,
+    openmetrics: Bytes,
+    json: Bytes,
+}
+
+impl Snapshot {
+    fn new(metric_families: Vec<MetricFamily>) -> Self {
+        let render = |format: Format| {
+            let mut buf = Vec::with_capacity(100_000);
+            format.encode(&metric_families, &mut buf).unwrap();
+            Bytes::from(buf)
+        };
+
+        Snapshot {
+            text: render(Format::Text),
+            openmetrics: render(Format::OpenMetrics),
+            json: render(Format::Json),
+            metric_families,
+        }
+    }
+
+    fn rendered(&self, format: Format) -> Bytes {
+        match format {
+            Format::Text => self.text.clone(),
+            Format::OpenMetrics => self.openmetrics.clone(),
+            Format::Json => self.json.clone(),
+        }
+    }
+}
+
+async fn refresh_snapshot(state: Arc<State>, interval: Duration) {
+    loop {
+        let metric_families = relabel(metrics::gather(), &state.relabel_rules);
+
+        state
+            .snapshot
+            .store(Some(Arc::new(Snapshot::new(metric_families))));
+
+        time::sleep(interval).await;
+    }
+}
+
+async fn handle(req: Request<Body>, state: Arc<State>) -> Result<Response<Body>, hyper::Error> {
     let span = span!(
         Level::INFO,
         "request",
@@ -39,21 +284,67 @@ async fn handle(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
     async move {
         let mut response = Response::new(Body::empty());
 
-        match (req.method(), req.uri().path()) {
+        let path = req
+            .uri()
+            .path()
+            .strip_prefix(state.route_prefix.as_str())
+            .unwrap_or_default();
+
+        match (req.method(), path) {
             (&Method::GET, "/healthz") => {
                 *response.body_mut() = Body::from("OK");
             }
+            (&Method::GET, "/ready") => {
+                if ready::is_ready() {
+                    *response.body_mut() = Body::from("OK");
+                } else {
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    *response.body_mut() = Body::from("waiting for the first poll of every task");
+                }
+            }
             (&Method::GET, "/metrics") => {
-                let mut buf = Vec::with_capacity(100_000);
-                let encoder = TextEncoder::new();
-                let metric_families = prometheus::gather();
-                encoder.encode(&metric_families, &mut buf).unwrap();
+                let format = Format::negotiate(req.headers().get(ACCEPT));
+                let org = query_param(&req, "org");
+
+                let body = match state.snapshot.load_full() {
+                    Some(snapshot) if org.is_none() => Body::from(snapshot.rendered(format)),
+                    snapshot => {
+                        let mut metric_families = match snapshot {
+                            Some(snapshot) => snapshot.metric_families.clone(),
+                            None => relabel(metrics::gather(), &state.relabel_rules),
+                        };
+                        if let Some(org) = org {
+                            metric_families = filter_by_org(metric_families, org);
+                        }
+
+                        if format.can_stream() {
+                            stream(format, metric_families)
+                        } else {
+                            let mut buf = Vec::with_capacity(100_000);
+                            format.encode(&metric_families, &mut buf).unwrap();
+                            Body::from(buf)
+                        }
+                    }
+                };
 
                 response
                     .headers_mut()
-                    .append(CONTENT_TYPE, encoder.format_type().parse().unwrap());
-                *response.body_mut() = Body::from(buf);
+                    .append(CONTENT_TYPE, format.content_type().parse().unwrap());
+                *response.body_mut() = body;
             }
+            (&Method::POST, "/pause") | (&Method::POST, "/resume") => match &state.admin_token {
+                None => *response.status_mut() = StatusCode::NOT_FOUND,
+                Some(token) if !is_authorized(&req, token) => {
+                    *response.status_mut() = StatusCode::UNAUTHORIZED;
+                }
+                Some(_) => {
+                    let paused = path == "/pause";
+                    pause::set_paused(paused);
+
+                    info!(paused, "polling pause toggled");
+                    *response.body_mut() = Body::from(if paused { "paused" } else { "resumed" });
+                }
+            },
             _ => {
                 *response.status_mut() = StatusCode::NOT_FOUND;
             }
@@ -72,6 +363,139 @@ async fn handle(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
     .await
 }
 
+/// Whether `req` carries `token` as a bearer token.
+fn is_authorized(req: &Request<Body>, token: &str) -> bool {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map_or(false, |value| value == token)
+}
+
+/// Encodes metrics one family at a time into a chunked body, so that the
+/// whole exposition is never buffered at once.
+fn stream(format: Format, metric_families: Vec<MetricFamily>) -> Body {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        for mf in metric_families {
+            let mut buf = Vec::new();
+
+            if let Err(err) = format.encode_families(slice::from_ref(&mf), &mut buf) {
+                error!("failed to encode metric family {}: {}", mf.get_name(), err);
+                sender.abort();
+                return;
+            }
+
+            // The client went away.
+            if sender.send_data(Bytes::from(buf)).await.is_err() {
+                return;
+            }
+        }
+
+        let mut buf = Vec::new();
+        format.finish(&mut buf).unwrap();
+
+        if !buf.is_empty() {
+            let _ = sender.send_data(Bytes::from(buf)).await;
+        }
+    });
+
+    body
+}
+
+/// Turns `gh-exporter/` or `/gh-exporter/` into `/gh-exporter`, and `/` into
+/// an empty prefix.
+fn normalize_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim_matches('/');
+
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+fn query_param<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Applies the relabel `rules` in order, dropping families left without series.
+fn relabel(mut metric_families: Vec<MetricFamily>, rules: &[RelabelRule]) -> Vec<MetricFamily> {
+    for rule in rules {
+        match rule {
+            RelabelRule::Drop { label, regex } => {
+                for mf in &mut metric_families {
+                    let metrics = mf
+                        .take_metric()
+                        .into_iter()
+                        .filter(|m| {
+                            let value = m
+                                .get_label()
+                                .iter()
+                                .find(|l| l.get_name() == label)
+                                .map_or("", |l| l.get_value());
+
+                            !regex.is_match(value)
+                        })
+                        .collect();
+                    mf.set_metric(metrics);
+                }
+            }
+            RelabelRule::Rename { from, to } => {
+                for mf in &mut metric_families {
+                    for m in mf.mut_metric().iter_mut() {
+                        let labels = m.mut_label();
+
+                        for l in labels.iter_mut().filter(|l| l.get_name() == from) {
+                            l.set_name(to.clone());
+                        }
+
+                        labels
+                            .as_mut_slice()
+                            .sort_by(|a, b| a.get_name().cmp(b.get_name()));
+                    }
+                }
+            }
+        }
+    }
+
+    metric_families.retain(|mf| !mf.get_metric().is_empty());
+    metric_families
+}
+
+/// Keeps only the series whose `organisation` or `owner` label equals `org`,
+/// dropping families that end up empty.
+fn filter_by_org(metric_families: Vec<MetricFamily>, org: &str) -> Vec<MetricFamily> {
+    metric_families
+        .into_iter()
+        .filter_map(|mut mf| {
+            let metrics = mf
+                .take_metric()
+                .into_iter()
+                .filter(|m| {
+                    m.get_label().iter().any(|l| {
+                        (l.get_name() == "organisation" || l.get_name() == "owner")
+                            && l.get_value() == org
+                    })
+                })
+                .collect();
+            mf.set_metric(metrics);
+
+            if mf.get_metric().is_empty() {
+                None
+            } else {
+                Some(mf)
+            }
+        })
+        .collect()
+}
+
 lazy_static! {
     pub static ref HTTP_COUNTER: IntCounterVec = register_int_counter_vec!(
         "http_requests_total",
@@ -86,3 +510,63 @@ lazy_static! {
     )
     .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown;
+    use hyper::{body, Client, Uri};
+
+    #[test]
+    fn parses_ipv6_bind() {
+        match Bind::from_str("[::1]:8000").unwrap() {
+            Bind::Tcp(addr) => {
+                assert!(addr.is_ipv6());
+                assert_eq!(addr.port(), 8000);
+            }
+            bind => panic!("unexpected bind {}", bind),
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_over_ipv6() {
+        // Skipped where IPv6 isn't available.
+        let addr = match std::net::TcpListener::bind("[::1]:0").and_then(|l| l.local_addr()) {
+            Ok(addr) => addr,
+            Err(_) => return,
+        };
+        let bind = Bind::from_str(&addr.to_string()).unwrap();
+        let (sender, shutdown) = shutdown::channel();
+
+        let client = async {
+            let uri = format!("http://{}/healthz", addr).parse::<Uri>().unwrap();
+            let mut response = None;
+
+            // Until the server is up.
+            for _ in 0..50 {
+                match Client::new().get(uri.clone()).await {
+                    Ok(r) => {
+                        response = Some(r);
+                        break;
+                    }
+                    Err(_) => time::sleep(Duration::from_millis(20)).await,
+                }
+            }
+
+            let response = match response {
+                Some(r) => Some((r.status(), body::to_bytes(r.into_body()).await.unwrap())),
+                None => None,
+            };
+
+            let _ = sender.send(true);
+            response
+        };
+
+        let (served, response) = tokio::join!(listen(&bind, Options::default(), shutdown), client);
+        served.unwrap();
+
+        let (status, body) = response.expect("no response over [::1]");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"OK");
+    }
+}
@@ -0,0 +1,452 @@
+use hyper::header::HeaderValue;
+use prometheus::{
+    proto::{LabelPair, Metric, MetricFamily, MetricType},
+    Encoder, TextEncoder,
+};
+use serde_json::{json, Map, Value};
+use std::io::Write;
+
+static OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+static JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Units announced with `# UNIT` for families whose name ends in one of them,
+/// as OpenMetrics requires.
+static UNITS: &[&str] = &["seconds", "bytes", "ms", "minutes", "gigabytes", "usd"];
+
+/// Exposition formats `/metrics` can be rendered in, picked from the
+/// request's `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+    Json,
+}
+
+impl Format {
+    /// Picks the format with the highest quality value in `accept`, falling
+    /// back to the Prometheus text format.
+    pub fn negotiate(accept: Option<&HeaderValue>) -> Self {
+        let accept = match accept.and_then(|v| v.to_str().ok()) {
+            Some(accept) => accept,
+            None => return Format::Text,
+        };
+
+        let mut best = (Format::Text, 0.0);
+
+        for media_range in accept.split(',') {
+            let mut params = media_range.split(';').map(str::trim);
+
+            let format = match params.next() {
+                Some("application/openmetrics-text") => Format::OpenMetrics,
+                Some("application/json") => Format::Json,
+                Some("text/plain") | Some("text/*") | Some("*/*") => Format::Text,
+                _ => continue,
+            };
+
+            let quality = params
+                .filter_map(|p| p.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if quality > best.1 {
+                best = (format, quality);
+            }
+        }
+
+        best.0
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => prometheus::TEXT_FORMAT,
+            Format::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+            Format::Json => JSON_CONTENT_TYPE,
+        }
+    }
+
+    pub fn encode(self, metric_families: &[MetricFamily], buf: &mut Vec<u8>) -> anyhow::Result<()> {
+        self.encode_families(metric_families, buf)?;
+        self.finish(buf)?;
+
+        Ok(())
+    }
+
+    /// Whether an exposition can be encoded a few families at a time, see
+    /// [`Format::encode_families`].
+    pub fn can_stream(self) -> bool {
+        self != Format::Json
+    }
+
+    /// Encodes `metric_families` without ending the exposition, so that
+    /// formats which [`can_stream`](Format::can_stream) may be written in
+    /// chunks before [`Format::finish`].
+    pub fn encode_families(
+        self,
+        metric_families: &[MetricFamily],
+        buf: &mut Vec<u8>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Format::Text => TextEncoder::new().encode(metric_families, buf)?,
+            Format::OpenMetrics => encode_openmetrics(metric_families, buf)?,
+            Format::Json => serde_json::to_writer(buf, &encode_json(metric_families))?,
+        }
+
+        Ok(())
+    }
+
+    /// Ends an exposition, which OpenMetrics marks with `# EOF`.
+    pub fn finish(self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        match self {
+            Format::OpenMetrics => writeln!(buf, "# EOF"),
+            Format::Text | Format::Json => Ok(()),
+        }
+    }
+}
+
+fn encode_openmetrics(metric_families: &[MetricFamily], w: &mut dyn Write) -> std::io::Result<()> {
+    for mf in metric_families {
+        let name = mf.get_name();
+
+        let (family, metric_type) = match mf.get_field_type() {
+            MetricType::COUNTER => (name.strip_suffix("_total").unwrap_or(name), "counter"),
+            MetricType::GAUGE => (name, "gauge"),
+            MetricType::HISTOGRAM => (name, "histogram"),
+            MetricType::SUMMARY => (name, "summary"),
+            MetricType::UNTYPED => (name, "unknown"),
+        };
+
+        writeln!(w, "# TYPE {} {}", family, metric_type)?;
+        if let Some(unit) = unit(family) {
+            writeln!(w, "# UNIT {} {}", family, unit)?;
+        }
+        writeln!(w, "# HELP {} {}", family, escape(mf.get_help()))?;
+
+        for m in mf.get_metric() {
+            match mf.get_field_type() {
+                MetricType::COUNTER => {
+                    let sample = format!("{}_total", family);
+                    write_sample(w, &sample, m, None, m.get_counter().get_value())?;
+                }
+                MetricType::GAUGE => {
+                    write_sample(w, family, m, None, m.get_gauge().get_value())?;
+                }
+                MetricType::UNTYPED => {
+                    write_sample(w, family, m, None, m.get_untyped().get_value())?;
+                }
+                MetricType::HISTOGRAM => {
+                    let h = m.get_histogram();
+                    let bucket = format!("{}_bucket", family);
+
+                    for b in h.get_bucket() {
+                        let le = format_value(b.get_upper_bound());
+                        let count = b.get_cumulative_count() as f64;
+                        write_sample(w, &bucket, m, Some(("le", le.as_str())), count)?;
+                    }
+
+                    let count = h.get_sample_count() as f64;
+                    write_sample(w, &bucket, m, Some(("le", "+Inf")), count)?;
+                    write_sample(w, &format!("{}_count", family), m, None, count)?;
+                    write_sample(w, &format!("{}_sum", family), m, None, h.get_sample_sum())?;
+                }
+                MetricType::SUMMARY => {
+                    let s = m.get_summary();
+
+                    for q in s.get_quantile() {
+                        let quantile = format_value(q.get_quantile());
+                        write_sample(
+                            w,
+                            family,
+                            m,
+                            Some(("quantile", quantile.as_str())),
+                            q.get_value(),
+                        )?;
+                    }
+
+                    let count = s.get_sample_count() as f64;
+                    write_sample(w, &format!("{}_count", family), m, None, count)?;
+                    write_sample(w, &format!("{}_sum", family), m, None, s.get_sample_sum())?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn unit(family: &str) -> Option<&'static str> {
+    UNITS.iter().copied().find(|unit| {
+        family
+            .strip_suffix(unit)
+            .map_or(false, |rest| rest.ends_with('_'))
+    })
+}
+
+fn write_sample(
+    w: &mut dyn Write,
+    name: &str,
+    m: &Metric,
+    extra_label: Option<(&str, &str)>,
+    value: f64,
+) -> std::io::Result<()> {
+    let labels = m
+        .get_label()
+        .iter()
+        .map(|l| (l.get_name(), l.get_value()))
+        .chain(extra_label)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>();
+
+    if labels.is_empty() {
+        writeln!(w, "{} {}", name, format_value(value))
+    } else {
+        writeln!(
+            w,
+            "{}{{{}}} {}",
+            name,
+            labels.join(","),
+            format_value(value)
+        )
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".into()
+    } else if v.is_infinite() && v.is_sign_positive() {
+        "+Inf".into()
+    } else if v.is_infinite() {
+        "-Inf".into()
+    } else {
+        v.to_string()
+    }
+}
+
+fn encode_json(metric_families: &[MetricFamily]) -> Value {
+    metric_families
+        .iter()
+        .map(|mf| {
+            let (metric_type, metrics) = match mf.get_field_type() {
+                MetricType::COUNTER => (
+                    "counter",
+                    json_samples(mf, |m| json!({ "value": m.get_counter().get_value() })),
+                ),
+                MetricType::GAUGE => (
+                    "gauge",
+                    json_samples(mf, |m| json!({ "value": m.get_gauge().get_value() })),
+                ),
+                MetricType::UNTYPED => (
+                    "untyped",
+                    json_samples(mf, |m| json!({ "value": m.get_untyped().get_value() })),
+                ),
+                MetricType::HISTOGRAM => (
+                    "histogram",
+                    json_samples(mf, |m| {
+                        let h = m.get_histogram();
+                        let buckets = h
+                            .get_bucket()
+                            .iter()
+                            .map(|b| {
+                                (
+                                    format_value(b.get_upper_bound()),
+                                    b.get_cumulative_count().into(),
+                                )
+                            })
+                            .collect::<Map<_, _>>();
+
+                        json!({
+                            "count": h.get_sample_count(),
+                            "sum": h.get_sample_sum(),
+                            "buckets": buckets,
+                        })
+                    }),
+                ),
+                MetricType::SUMMARY => (
+                    "summary",
+                    json_samples(mf, |m| {
+                        let s = m.get_summary();
+                        let quantiles = s
+                            .get_quantile()
+                            .iter()
+                            .map(|q| (format_value(q.get_quantile()), q.get_value().into()))
+                            .collect::<Map<_, _>>();
+
+                        json!({
+                            "count": s.get_sample_count(),
+                            "sum": s.get_sample_sum(),
+                            "quantiles": quantiles,
+                        })
+                    }),
+                ),
+            };
+
+            json!({
+                "name": mf.get_name(),
+                "help": mf.get_help(),
+                "type": metric_type,
+                "metrics": metrics,
+            })
+        })
+        .collect()
+}
+
+fn json_samples(mf: &MetricFamily, sample: impl Fn(&Metric) -> Value) -> Value {
+    mf.get_metric()
+        .iter()
+        .map(|m| {
+            let mut value = sample(m);
+            value["labels"] = json_labels(m.get_label());
+            value
+        })
+        .collect()
+}
+
+fn json_labels(labels: &[LabelPair]) -> Value {
+    labels
+        .iter()
+        .map(|l| (l.get_name().to_string(), l.get_value().into()))
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{GaugeVec, IntCounter, Opts, Registry};
+
+    fn negotiate(accept: &str) -> Format {
+        Format::negotiate(Some(&HeaderValue::from_str(accept).unwrap()))
+    }
+
+    fn metric_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+
+        let counter = IntCounter::new("test_requests_total", "Requests \"sent\"").unwrap();
+        counter.inc_by(3);
+        registry.register(Box::new(counter)).unwrap();
+
+        let gauge = GaugeVec::new(
+            Opts::new("test_duration_seconds", "Duration"),
+            &["repository"],
+        )
+        .unwrap();
+        gauge.with_label_values(&["exporter"]).set(1.5);
+        registry.register(Box::new(gauge)).unwrap();
+
+        registry.gather()
+    }
+
+    fn encode(format: Format) -> String {
+        let mut buf = Vec::new();
+        format.encode(&metric_families(), &mut buf).unwrap();
+
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn negotiates_text_without_accept() {
+        assert_eq!(Format::negotiate(None), Format::Text);
+    }
+
+    #[test]
+    fn negotiates_each_format() {
+        assert_eq!(negotiate("text/plain; version=0.0.4"), Format::Text);
+        assert_eq!(
+            negotiate("application/openmetrics-text; version=1.0.0"),
+            Format::OpenMetrics
+        );
+        assert_eq!(negotiate("application/json"), Format::Json);
+    }
+
+    #[test]
+    fn negotiates_highest_quality() {
+        assert_eq!(
+            negotiate("application/json;q=0.5, application/openmetrics-text;q=0.9"),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            negotiate("text/plain;q=0.2, application/json"),
+            Format::Json
+        );
+        // What Prometheus sends by default.
+        assert_eq!(
+            negotiate("application/openmetrics-text;version=1.0.0,application/openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1"),
+            Format::OpenMetrics
+        );
+    }
+
+    #[test]
+    fn falls_back_to_text() {
+        assert_eq!(negotiate("image/png"), Format::Text);
+        assert_eq!(negotiate("*/*"), Format::Text);
+        assert_eq!(negotiate("not a media type"), Format::Text);
+    }
+
+    #[test]
+    fn encodes_text() {
+        let text = encode(Format::Text);
+
+        assert!(text.contains("# TYPE test_requests_total counter\n"));
+        assert!(text.contains("test_requests_total 3\n"));
+        assert!(text.contains("# TYPE test_duration_seconds gauge\n"));
+        assert!(text.contains("test_duration_seconds{repository=\"exporter\"} 1.5\n"));
+    }
+
+    #[test]
+    fn encodes_openmetrics() {
+        let text = encode(Format::OpenMetrics);
+
+        assert!(text.contains("# TYPE test_requests counter\n"));
+        assert!(text.contains("# HELP test_requests Requests \\\"sent\\\"\n"));
+        assert!(text.contains("test_requests_total 3\n"));
+        assert!(text.contains("# TYPE test_duration_seconds gauge\n"));
+        assert!(text.contains("# UNIT test_duration_seconds seconds\n"));
+        assert!(text.contains("test_duration_seconds{repository=\"exporter\"} 1.5\n"));
+        assert!(!text.contains("# UNIT test_requests "));
+        assert!(text.ends_with("# EOF\n"));
+        assert_eq!(text.matches("# EOF").count(), 1);
+    }
+
+    #[test]
+    fn encodes_json() {
+        let json = serde_json::from_str::<Value>(&encode(Format::Json)).unwrap();
+        let families = json.as_array().unwrap();
+
+        let counter = families
+            .iter()
+            .find(|mf| mf["name"] == "test_requests_total")
+            .unwrap();
+        assert_eq!(counter["type"], "counter");
+        assert_eq!(counter["metrics"][0]["value"], 3.0);
+
+        let gauge = families
+            .iter()
+            .find(|mf| mf["name"] == "test_duration_seconds")
+            .unwrap();
+        assert_eq!(gauge["type"], "gauge");
+        assert_eq!(gauge["metrics"][0]["value"], 1.5);
+        assert_eq!(gauge["metrics"][0]["labels"]["repository"], "exporter");
+    }
+
+    #[test]
+    fn encodes_the_same_in_chunks() {
+        for format in [Format::Text, Format::OpenMetrics] {
+            let mut buf = Vec::new();
+
+            for mf in &metric_families() {
+                format
+                    .encode_families(std::slice::from_ref(mf), &mut buf)
+                    .unwrap();
+            }
+            format.finish(&mut buf).unwrap();
+
+            assert_eq!(String::from_utf8(buf).unwrap(), encode(format));
+        }
+    }
+}
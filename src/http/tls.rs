@@ -0,0 +1,67 @@
+use anyhow::{anyhow, Context, Result};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use tokio_rustls::rustls::{
+    internal::pemfile, AllowAnyAuthenticatedClient, NoClientAuth, PrivateKey, RootCertStore,
+    ServerConfig,
+};
+
+/// Loads the certificate chain and key the listener presents. With
+/// `client_ca`, clients must present a certificate signed by one of its CAs.
+///
+/// Everything is checked here, so that a bad file fails the startup rather
+/// than the first scrape.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>> {
+    let certs = pemfile::certs(&mut open(cert)?)
+        .map_err(|()| anyhow!("invalid certificate in {}", cert.display()))?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {}", cert.display()));
+    }
+
+    let client_auth = match client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            let (valid, _) = roots
+                .add_pem_file(&mut open(path)?)
+                .map_err(|()| anyhow!("invalid CA certificate in {}", path.display()))?;
+
+            if valid == 0 {
+                return Err(anyhow!("no CA certificate found in {}", path.display()));
+            }
+
+            AllowAnyAuthenticatedClient::new(roots)
+        }
+        None => NoClientAuth::new(),
+    };
+
+    let mut config = ServerConfig::new(client_auth);
+    config
+        .set_single_cert(certs, private_key(key)?)
+        .with_context(|| format!("key in {} does not match the certificate", key.display()))?;
+
+    Ok(Arc::new(config))
+}
+
+/// First PKCS#8 or RSA private key in `path`.
+fn private_key(path: &Path) -> Result<PrivateKey> {
+    let invalid = |()| anyhow!("invalid private key in {}", path.display());
+
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut open(path)?).map_err(invalid)?;
+    let rsa = pemfile::rsa_private_keys(&mut open(path)?).map_err(invalid)?;
+
+    pkcs8
+        .into_iter()
+        .chain(rsa)
+        .next()
+        .ok_or_else(|| anyhow!("no private key found in {}", path.display()))
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+
+    Ok(BufReader::new(file))
+}
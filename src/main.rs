@@ -1,16 +1,72 @@
-use crate::types::{Organisation, Repository, Workflow};
-use anyhow::Result;
+use crate::{
+    repositories::Repositories,
+    rotation::Rotation,
+    types::{
+        ApiHeader, BillableUnit, Collector, NamePattern, Organisation, Organisations, OwnerToken,
+        RelabelRule, Repository, StaticLabel, WorkflowPattern,
+    },
+};
+use anyhow::{anyhow, Result};
+use arc_swap::ArcSwap;
 use clap::{
     crate_authors, crate_description, crate_name, crate_version, value_t, values_t, App, Arg,
+    ArgMatches,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
-use octocrab::Octocrab;
-use std::{collections::HashMap, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
-use tokio::sync::RwLock;
-use tracing::{info, Level};
+use tokio::{task::JoinError, time};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
+mod check;
+mod config;
+mod expiry;
+mod github;
 mod http;
+mod metrics;
+mod otlp;
+mod pause;
+mod ready;
+mod repositories;
+mod rotation;
+mod shutdown;
 mod tasks;
 mod types;
+mod watchdog;
+
+/// How long poll tasks get to stop after a termination signal.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Values of the delimited arg `name`, leaving out empty entries such as the
+/// `""` default or what a stray delimiter yields.
+fn non_empty_values<T>(matches: &ArgMatches, name: &str) -> Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    matches
+        .values_of(name)
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            T::from_str(value)
+                .map_err(|err| anyhow!("invalid value `{}` for {}: {}", value, name, err))
+        })
+        .collect()
+}
+
+/// Whether `name` was given on the command line or through `env`, rather than
+/// left to its default value.
+fn is_explicit(matches: &ArgMatches, name: &str, env: &str) -> bool {
+    matches.occurrences_of(name) > 0 || std::env::var_os(env).is_some()
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -20,24 +76,84 @@ async fn main() -> Result<()> {
         .about(crate_description!())
         .arg(
             Arg::with_name("bind")
-                .help("bind to address")
+                .help("bind to address, or to a unix socket given as unix:/path/to.sock")
                 .long("bind")
                 .short("b")
                 .env("GH_EXPORTER_BIND")
-                .validator(|s: String| {
-                    SocketAddr::from_str(&s)
-                        .map(|_| ())
-                        .map_err(|err| err.to_string())
-                })
+                .validator(|s: String| http::Bind::from_str(&s).map(|_| ()))
                 .default_value("0.0.0.0:8000"),
         )
+        .arg(
+            Arg::with_name("config_file")
+                .help("YAML file with repos, orgs, poll_interval, workflows_refresh and token, overridden by flags and environment variables; repos and orgs are reloaded on change")
+                .long("config-file")
+                .env("GH_EXPORTER_CONFIG_FILE"),
+        )
+        .arg(
+            Arg::with_name("config_reload_interval")
+                .help("interval when to check the config file for changes (in seconds)")
+                .long("config-reload-interval")
+                .env("GH_EXPORTER_CONFIG_RELOAD_INTERVAL")
+                .default_value("60"),
+        )
         .arg(
             Arg::with_name("github_token")
                 .help("GitHub token")
                 .long("github-token")
                 .short("t")
                 .env("GH_TOKEN")
-                .required(true),
+                .required_unless_one(&[
+                    "github_token_file",
+                    "github_app_id",
+                    "config_file",
+                    "github_token_map",
+                ]),
+        )
+        .arg(
+            Arg::with_name("github_token_file")
+                .help("file to read the GitHub token from, re-read periodically so the token can be rotated without a restart")
+                .long("github-token-file")
+                .env("GH_TOKEN_FILE")
+                .conflicts_with("github_token"),
+        )
+        .arg(
+            Arg::with_name("github_app_id")
+                .help("authenticate as this GitHub App instead of with a token, minting installation tokens as they expire")
+                .long("github-app-id")
+                .env("GH_APP_ID")
+                .conflicts_with_all(&["github_token", "github_token_file"])
+                .requires_all(&["github_app_private_key", "github_app_installation_id"])
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("github_app_private_key")
+                .help("path to the PEM private key of the GitHub App")
+                .long("github-app-private-key")
+                .env("GH_APP_PRIVATE_KEY")
+                .requires("github_app_id"),
+        )
+        .arg(
+            Arg::with_name("github_app_installation_id")
+                .help("id of the GitHub App installation to authenticate as")
+                .long("github-app-installation-id")
+                .env("GH_APP_INSTALLATION_ID")
+                .requires("github_app_id")
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("github_token_reload_interval")
+                .help("interval when to check the GitHub token file for changes (in seconds)")
+                .long("github-token-reload-interval")
+                .env("GH_TOKEN_RELOAD_INTERVAL")
+                .default_value("60"),
         )
         .arg(
             Arg::with_name("github_orgs")
@@ -49,6 +165,54 @@ async fn main() -> Result<()> {
                 .env("GH_ORGS")
                 .default_value("")
         )
+        .arg(
+            Arg::with_name("github_users")
+                .help("GitHub users whose personal account billing is polled, delimited by `,`")
+                .long("github-users")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_USERS")
+                .default_value("")
+        )
+        .arg(
+            Arg::with_name("github_orgs_discover_repos")
+                .help("poll all repositories of the GitHub organisations, discovered on the workflows refresh interval")
+                .long("github-orgs-discover-repos"),
+        )
+        .arg(
+            Arg::with_name("repo_include")
+                .help("only poll discovered repositories whose owner/repo matches one of these case-insensitive patterns, where `*` matches anything, delimited by `,`")
+                .long("repo-include")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_REPO_INCLUDE")
+                .requires("github_orgs_discover_repos")
+                .validator(|s: String| {
+                    NamePattern::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("repo_exclude")
+                .help("never poll discovered repositories whose owner/repo matches one of these case-insensitive patterns, taking precedence over --repo-include, delimited by `,`")
+                .long("repo-exclude")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_REPO_EXCLUDE")
+                .requires("github_orgs_discover_repos")
+                .validator(|s: String| {
+                    NamePattern::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("github_orgs_include_archived")
+                .help("include archived repositories when discovering organisation repositories")
+                .long("github-orgs-include-archived")
+                .requires("github_orgs_discover_repos"),
+        )
         .arg(
             Arg::with_name("github_repos")
                 .help("GitHub repos list, formatted as owner/repo, delimited by `,`")
@@ -66,6 +230,36 @@ async fn main() -> Result<()> {
                 .short("u")
                 .env("GH_API_BASEURL")
         )
+        .arg(
+            Arg::with_name("github_enterprise")
+                .help("the GitHub API is a GitHub Enterprise Server, which has no organisation or user billing endpoints")
+                .long("github-enterprise"),
+        )
+        .arg(
+            Arg::with_name("github_token_map")
+                .help("token used for the repositories, billing etc. of one owner instead of the main token, formatted as owner=token, delimited by `,`")
+                .long("github-token-map")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_TOKEN_MAP")
+                .validator(|s: String| {
+                    OwnerToken::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("api_header")
+                .help("extra header sent with every GitHub API request, formatted as name=value (repeatable)")
+                .long("api-header")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|s: String| {
+                    ApiHeader::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
         .arg(
             Arg::with_name("github_workflows_refresh")
                 .help("interval when to refresh workflows cache for each GitHub repository (in seconds)")
@@ -74,6 +268,43 @@ async fn main() -> Result<()> {
                 .env("GH_WORKFLOWS_REFRESH")
                 .default_value("1800"),
         )
+        .arg(
+            Arg::with_name("workflow_include")
+                .help("only poll workflows whose name matches one of these case-insensitive patterns, where `*` matches anything, delimited by `,`")
+                .long("workflow-include")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_WORKFLOW_INCLUDE")
+                .validator(|s: String| {
+                    NamePattern::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("workflow_exclude")
+                .help("never poll workflows whose name matches one of these case-insensitive patterns, taking precedence over --workflow-include, delimited by `,`")
+                .long("workflow-exclude")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_WORKFLOW_EXCLUDE")
+                .validator(|s: String| {
+                    NamePattern::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("max_workflows_per_repo")
+                .help("maximum number of workflows polled for each GitHub repository")
+                .long("max-workflows-per-repo")
+                .env("GH_MAX_WORKFLOWS_PER_REPO")
+                .validator(|s: String| {
+                    usize::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
         .arg(
             Arg::with_name("github_poll_interval")
                 .help("poll interval from GitHub API (in seconds)")
@@ -82,64 +313,962 @@ async fn main() -> Result<()> {
                 .env("GH_POLL_INTERVAL")
                 .default_value("300"),
         )
+        .arg(
+            Arg::with_name("billing_poll_interval")
+                .help("poll interval of organisation and user billing, which changes slowly (in seconds); the GitHub poll interval when unset")
+                .long("billing-poll-interval")
+                .env("GH_BILLING_POLL_INTERVAL")
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("timing_poll_interval")
+                .help("poll interval of workflow billable time (in seconds); the GitHub poll interval when unset")
+                .long("timing-poll-interval")
+                .env("GH_TIMING_POLL_INTERVAL")
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("contributors_poll_interval")
+                .help("poll interval of repository contributors, which change slowly (in seconds)")
+                .long("contributors-poll-interval")
+                .env("GH_CONTRIBUTORS_POLL_INTERVAL")
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .default_value("21600"),
+        )
+        .arg(
+            Arg::with_name("poll_concurrency")
+                .help("number of repositories or workflows polled at the same time when refreshing workflows and polling billable time")
+                .long("poll-concurrency")
+                .env("GH_POLL_CONCURRENCY")
+                .validator(|s: String| match usize::from_str(&s) {
+                    Ok(0) => Err("poll concurrency must be at least 1".into()),
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .default_value("8"),
+        )
+        .arg(
+            Arg::with_name("poll_fraction")
+                .help("fraction of repositories polled on each cycle, rotating through all of them over several cycles")
+                .long("poll-fraction")
+                .env("GH_POLL_FRACTION")
+                .validator(|s: String| match f64::from_str(&s) {
+                    Ok(f) if f > 0.0 && f <= 1.0 => Ok(()),
+                    Ok(_) => Err("poll fraction must be in (0, 1]".into()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("pending_threshold")
+                .help("age after which a queued or waiting workflow run counts as pending for too long (in seconds)")
+                .long("pending-threshold")
+                .env("GH_PENDING_THRESHOLD")
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .default_value("600"),
+        )
+        .arg(
+            Arg::with_name("github_timeout")
+                .help("time after which a GitHub API request is abandoned, from connecting to reading the response (in seconds)")
+                .long("github-timeout")
+                .env("GH_TIMEOUT")
+                .default_value("30"),
+        )
+        .arg(
+            Arg::with_name("max_retries")
+                .help("maximum number of retries of GitHub API requests failing with network or server errors")
+                .long("max-retries")
+                .env("GH_MAX_RETRIES")
+                .validator(|s: String| {
+                    u32::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("retry_base_delay")
+                .help("delay before retrying a failed GitHub API request, doubled on each further retry (in milliseconds)")
+                .long("retry-base-delay")
+                .env("GH_RETRY_BASE_DELAY")
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .default_value("500"),
+        )
+        .arg(
+            Arg::with_name("run_duration_buckets")
+                .help("buckets of the workflow run duration histogram, in increasing order (in seconds), delimited by `,`")
+                .long("run-duration-buckets")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_RUN_DURATION_BUCKETS")
+                .validator(|s: String| {
+                    f64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .default_value("30,60,120,300,600,900,1800,3600,7200,21600"),
+        )
+        .arg(
+            Arg::with_name("billable_unit")
+                .help("unit in which workflow billable time is exported")
+                .long("billable-unit")
+                .env("GH_BILLABLE_UNIT")
+                .possible_values(&["milliseconds", "seconds"])
+                .default_value("milliseconds"),
+        )
+        .arg(
+            Arg::with_name("billable_poll_divisor")
+                .help("poll each workflow's billable time only every N poll intervals, staggered across workflows")
+                .long("billable-poll-divisor")
+                .env("GH_BILLABLE_POLL_DIVISOR")
+                .validator(|s: String| match u64::from_str(&s) {
+                    Ok(0) => Err("billable poll divisor must be at least 1".into()),
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("billable_exclude_workflows")
+                .help("workflows whose billable time is not polled, formatted as [owner/repo:]workflow where `*` matches anything, delimited by `,`")
+                .long("billable-exclude-workflows")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_BILLABLE_EXCLUDE_WORKFLOWS")
+                .validator(|s: String| {
+                    WorkflowPattern::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("omit_zeros")
+                .help("remove billing series whose value is zero instead of exporting zeros")
+                .long("omit-zeros"),
+        )
+        .arg(
+            Arg::with_name("cost_ubuntu_per_min")
+                .help("USD per Ubuntu Actions minute, for estimated costs")
+                .long("cost-ubuntu-per-min")
+                .env("GH_COST_UBUNTU_PER_MIN")
+                .default_value("0.008"),
+        )
+        .arg(
+            Arg::with_name("cost_macos_per_min")
+                .help("USD per macOS Actions minute, for estimated costs")
+                .long("cost-macos-per-min")
+                .env("GH_COST_MACOS_PER_MIN")
+                .default_value("0.08"),
+        )
+        .arg(
+            Arg::with_name("cost_windows_per_min")
+                .help("USD per Windows Actions minute, for estimated costs")
+                .long("cost-windows-per-min")
+                .env("GH_COST_WINDOWS_PER_MIN")
+                .default_value("0.016"),
+        )
+        .arg(
+            Arg::with_name("cost_storage_per_gb")
+                .help("USD per GB of paid shared storage for the month, for estimated costs")
+                .long("cost-storage-per-gb")
+                .env("GH_COST_STORAGE_PER_GB")
+                .default_value("0.25"),
+        )
+        .arg(
+            Arg::with_name("cost_bandwidth_per_gb")
+                .help("USD per GB of paid Packages bandwidth, for estimated costs")
+                .long("cost-bandwidth-per-gb")
+                .env("GH_COST_BANDWIDTH_PER_GB")
+                .default_value("0.5"),
+        )
+        .arg(
+            Arg::with_name("instance_id")
+                .help("value of an instance_id label added to all metrics")
+                .long("instance-id")
+                .env("GH_EXPORTER_INSTANCE_ID"),
+        )
+        .arg(
+            Arg::with_name("static_labels")
+                .help("label added to all metrics, e.g. environment or team, formatted as name=value (repeatable)")
+                .long("static-labels")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|s: String| {
+                    StaticLabel::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("route_prefix")
+                .help("path prefix all HTTP routes are served under, e.g. /gh-exporter")
+                .long("route-prefix")
+                .env("GH_EXPORTER_ROUTE_PREFIX")
+                .default_value(""),
+        )
+        .arg(
+            Arg::with_name("admin_token")
+                .help("bearer token required by the POST /pause and /resume routes, which are disabled without it")
+                .long("admin-token")
+                .env("GH_EXPORTER_ADMIN_TOKEN"),
+        )
+        .arg(
+            Arg::with_name("relabel")
+                .help("rule applied to the exposed series, either drop:label=regex or rename:from=to (repeatable)")
+                .long("relabel")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|s: String| {
+                    RelabelRule::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("tls_cert")
+                .help("path to the PEM certificate chain to serve HTTPS with")
+                .long("tls-cert")
+                .env("GH_EXPORTER_TLS_CERT")
+                .requires("tls_key"),
+        )
+        .arg(
+            Arg::with_name("tls_key")
+                .help("path to the PEM private key of the TLS certificate")
+                .long("tls-key")
+                .env("GH_EXPORTER_TLS_KEY")
+                .requires("tls_cert"),
+        )
+        .arg(
+            Arg::with_name("tls_client_ca")
+                .help("path to the PEM CA certificates client certificates must be signed by, requiring them")
+                .long("tls-client-ca")
+                .env("GH_EXPORTER_TLS_CLIENT_CA")
+                .requires("tls_cert"),
+        )
+        .arg(
+            Arg::with_name("metrics_snapshot_interval")
+                .help("pre-render /metrics on this interval instead of on every scrape (in seconds)")
+                .long("metrics-snapshot-interval")
+                .env("GH_EXPORTER_METRICS_SNAPSHOT_INTERVAL")
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("otlp_endpoint")
+                .help("push metrics to this OTLP/HTTP endpoint, e.g. http://localhost:4318/v1/metrics")
+                .long("otlp-endpoint")
+                .env("GH_EXPORTER_OTLP_ENDPOINT")
+                .validator(|s: String| match hyper::Uri::from_str(&s) {
+                    Ok(uri) if uri.scheme_str() == Some("http") => Ok(()),
+                    Ok(_) => Err("OTLP endpoint must be an http:// url".into()),
+                    Err(err) => Err(err.to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("otlp_interval")
+                .help("interval between pushes to the OTLP endpoint (in seconds)")
+                .long("otlp-interval")
+                .env("GH_EXPORTER_OTLP_INTERVAL")
+                .default_value("60"),
+        )
+        .arg(
+            Arg::with_name("warmup")
+                .help("wait for every poll task to complete a cycle before serving metrics, so that the first scrape has data")
+                .long("warmup"),
+        )
+        .arg(
+            Arg::with_name("warmup_timeout")
+                .help("maximum time to wait for the warmup before serving metrics anyway (in seconds)")
+                .long("warmup-timeout")
+                .env("GH_EXPORTER_WARMUP_TIMEOUT")
+                .validator(|s: String| {
+                    u64::from_str(&s)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .default_value("120"),
+        )
+        .arg(
+            Arg::with_name("metric_max_age")
+                .help("remove series whose data has not been refreshed for this long, e.g. while GitHub is unreachable (in seconds); kept forever by default")
+                .long("metric-max-age")
+                .env("GH_EXPORTER_METRIC_MAX_AGE"),
+        )
+        .arg(
+            Arg::with_name("log_format")
+                .help("format of the logs")
+                .long("log-format")
+                .env("GH_EXPORTER_LOG_FORMAT")
+                .possible_values(&["json", "pretty", "compact"])
+                .default_value("json"),
+        )
+        .arg(
+            Arg::with_name("log_level")
+                .help("log filter, a level like debug or per-module directives like info,github_exporter::github=debug; RUST_LOG is used when unset, INFO by default")
+                .long("log-level")
+                .env("GH_EXPORTER_LOG_LEVEL"),
+        )
+        .arg(
+            Arg::with_name("watchdog")
+                .help("exit when a poll task stops making progress, so that it can be restarted")
+                .long("watchdog"),
+        )
+        .arg(
+            Arg::with_name("collectors")
+                .help("metrics polled on top of the workflow and billing ones, each costing extra API requests: cache_usage, collaborators, contributors, deployments, orgs, queued_runs, repos, runners, schedule_drift, selfhosted_jobs, workflow_last_run, workflow_runs, delimited by `,`")
+                .long("collectors")
+                .multiple(true)
+                .use_delimiter(true)
+                .env("GH_COLLECTORS")
+                .validator(|s: String| {
+                    Collector::from_str(&s)
+                        .map(|_| ())
+                        .map_err(String::from)
+                }),
+        )
+        .arg(
+            Arg::with_name("poll_org_runners")
+                .help("also poll the self-hosted runners of the GitHub organisations, which requires organisation admin access")
+                .long("poll-org-runners"),
+        )
+        .arg(
+            Arg::with_name("check")
+                .help("check that the token, repositories and billing endpoints are accessible, then exit instead of polling")
+                .long("check"),
+        )
+        .arg(
+            Arg::with_name("no_http")
+                .help("do not start the HTTP listener, only run the poll tasks")
+                .long("no-http"),
+        )
         .get_matches();
 
-    let bind_to = value_t!(matches, "bind", SocketAddr)?;
-    let github_base_url = matches.value_of("github_base_url");
-    let github_token = value_t!(matches, "github_token", String)?;
-    let github_repos = if let None | Some("") = matches.value_of("github_repos") {
-        Default::default()
+    let bind_to = value_t!(matches, "bind", http::Bind)?;
+    let github_base_url = matches.value_of("github_api_baseurl");
+    let github_enterprise = matches.is_present("github_enterprise");
+    let github_token_file = matches.value_of("github_token_file").map(PathBuf::from);
+    let github_app = match matches.value_of("github_app_private_key") {
+        Some(path) => Some(github::AppInstallation {
+            id: value_t!(matches, "github_app_id", u64)?,
+            installation_id: value_t!(matches, "github_app_installation_id", u64)?,
+            private_key: github::read_private_key(Path::new(path)).await?,
+        }),
+        None => None,
+    };
+    let github_token_reload_interval =
+        Duration::from_secs(value_t!(matches, "github_token_reload_interval", u64)?);
+    let config_file = matches.value_of("config_file").map(PathBuf::from);
+    let config = match &config_file {
+        Some(path) => config::Config::read(path).await?,
+        None => config::Config::default(),
+    };
+    let config_reload_interval =
+        Duration::from_secs(value_t!(matches, "config_reload_interval", u64)?);
+    let github_repos = non_empty_values::<Repository>(&matches, "github_repos")?;
+    let repos_from_config = github_repos.is_empty();
+    let github_repos = if repos_from_config {
+        config.repos.clone()
+    } else {
+        github_repos
+    };
+    let github_orgs = non_empty_values::<Organisation>(&matches, "github_orgs")?;
+    let orgs_from_config = github_orgs.is_empty();
+    let github_orgs: Organisations = Arc::new(ArcSwap::from_pointee(if orgs_from_config {
+        config.orgs.clone()
+    } else {
+        github_orgs
+    }));
+    let github_users = non_empty_values::<String>(&matches, "github_users")?;
+    let discover_repos = matches.is_present("github_orgs_discover_repos");
+    let include_archived = matches.is_present("github_orgs_include_archived");
+    let github_token_map = if matches.is_present("github_token_map") {
+        values_t!(matches, "github_token_map", OwnerToken)?
+    } else {
+        Vec::new()
+    };
+    let api_headers = if matches.is_present("api_header") {
+        values_t!(matches, "api_header", ApiHeader)?
+    } else {
+        Vec::new()
+    };
+    let poll_interval = Duration::from_secs(match config.poll_interval {
+        Some(secs) if !is_explicit(&matches, "github_poll_interval", "GH_POLL_INTERVAL") => secs,
+        _ => value_t!(matches, "github_poll_interval", u64)?,
+    });
+    let workflows_refresh_interval = Duration::from_secs(match config.workflows_refresh {
+        Some(secs)
+            if !is_explicit(&matches, "github_workflows_refresh", "GH_WORKFLOWS_REFRESH") =>
+        {
+            secs
+        }
+        _ => value_t!(matches, "github_workflows_refresh", u64)?,
+    });
+    let repo_include = if matches.is_present("repo_include") {
+        values_t!(matches, "repo_include", NamePattern)?
+    } else {
+        Vec::new()
+    };
+    let repo_exclude = if matches.is_present("repo_exclude") {
+        values_t!(matches, "repo_exclude", NamePattern)?
+    } else {
+        Vec::new()
+    };
+    let workflow_include = if matches.is_present("workflow_include") {
+        values_t!(matches, "workflow_include", NamePattern)?
+    } else {
+        Vec::new()
+    };
+    let workflow_exclude = if matches.is_present("workflow_exclude") {
+        values_t!(matches, "workflow_exclude", NamePattern)?
+    } else {
+        Vec::new()
+    };
+    let max_workflows_per_repo = matches
+        .value_of("max_workflows_per_repo")
+        .map(usize::from_str)
+        .transpose()?;
+    let billing_poll_interval = matches
+        .value_of("billing_poll_interval")
+        .map(u64::from_str)
+        .transpose()?
+        .map_or(poll_interval, Duration::from_secs);
+    let timing_poll_interval = matches
+        .value_of("timing_poll_interval")
+        .map(u64::from_str)
+        .transpose()?
+        .map_or(poll_interval, Duration::from_secs);
+    let contributors_poll_interval =
+        Duration::from_secs(value_t!(matches, "contributors_poll_interval", u64)?);
+    let poll_concurrency = value_t!(matches, "poll_concurrency", usize)?;
+    let poll_fraction = value_t!(matches, "poll_fraction", f64)?;
+    let retry_policy = github::RetryPolicy {
+        max_retries: value_t!(matches, "max_retries", u32)?,
+        base_delay: Duration::from_millis(value_t!(matches, "retry_base_delay", u64)?),
+    };
+    let github_timeout = Duration::from_secs(value_t!(matches, "github_timeout", u64)?);
+    let pending_threshold = Duration::from_secs(value_t!(matches, "pending_threshold", u64)?);
+    let run_duration_buckets = values_t!(matches, "run_duration_buckets", f64)?;
+    if run_duration_buckets.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(anyhow!("run duration buckets must be in increasing order"));
+    }
+    let billable_unit = value_t!(matches, "billable_unit", BillableUnit)?;
+    let billable_poll_divisor = value_t!(matches, "billable_poll_divisor", u64)?;
+    let billable_exclude_workflows = if matches.is_present("billable_exclude_workflows") {
+        values_t!(matches, "billable_exclude_workflows", WorkflowPattern)?
     } else {
-        values_t!(matches, "github_repos", Repository)?
+        Vec::new()
     };
-    let github_orgs = if let None | Some("") = matches.value_of("github_orgs") {
-        Default::default()
+    let omit_zeros = matches.is_present("omit_zeros");
+    // Defaults are GitHub's published public prices, actual costs may differ.
+    let cost_rates = tasks::CostRates {
+        ubuntu_per_min: value_t!(matches, "cost_ubuntu_per_min", f64)?,
+        macos_per_min: value_t!(matches, "cost_macos_per_min", f64)?,
+        windows_per_min: value_t!(matches, "cost_windows_per_min", f64)?,
+        storage_per_gb: value_t!(matches, "cost_storage_per_gb", f64)?,
+        bandwidth_per_gb: value_t!(matches, "cost_bandwidth_per_gb", f64)?,
+    };
+    let instance_id = matches.value_of("instance_id");
+    let static_labels = if matches.is_present("static_labels") {
+        values_t!(matches, "static_labels", StaticLabel)?
+    } else {
+        Vec::new()
+    };
+    let mut static_label_names = instance_id
+        .map(|_| "instance_id")
+        .into_iter()
+        .collect::<HashSet<_>>();
+    if let Some(label) = static_labels
+        .iter()
+        .find(|label| !static_label_names.insert(label.name.as_str()))
+    {
+        return Err(anyhow!(
+            "static label `{}` is given more than once, or also through --instance-id",
+            label.name
+        ));
+    }
+    let route_prefix = value_t!(matches, "route_prefix", String)?;
+    let admin_token = matches.value_of("admin_token").map(String::from);
+    let relabel_rules = if matches.is_present("relabel") {
+        values_t!(matches, "relabel", RelabelRule)?
     } else {
-        Arc::new(values_t!(matches, "github_orgs", Organisation)?)
+        Vec::new()
+    };
+    let tls = match (matches.value_of("tls_cert"), matches.value_of("tls_key")) {
+        (Some(cert), Some(key)) => Some(http::tls::server_config(
+            Path::new(cert),
+            Path::new(key),
+            matches.value_of("tls_client_ca").map(Path::new),
+        )?),
+        _ => None,
     };
-    let poll_interval = Duration::from_secs(value_t!(matches, "github_poll_interval", u64)?);
-    let workflows_refresh_interval =
-        Duration::from_secs(value_t!(matches, "github_workflows_refresh", u64)?);
+    let metrics_snapshot_interval = matches
+        .value_of("metrics_snapshot_interval")
+        .map(u64::from_str)
+        .transpose()?
+        .map(Duration::from_secs);
+    let otlp_endpoint = matches
+        .value_of("otlp_endpoint")
+        .map(hyper::Uri::from_str)
+        .transpose()?;
+    let otlp_interval = Duration::from_secs(value_t!(matches, "otlp_interval", u64)?);
+    let warmup = matches.is_present("warmup");
+    let warmup_timeout = Duration::from_secs(value_t!(matches, "warmup_timeout", u64)?);
+    let metric_max_age = matches
+        .value_of("metric_max_age")
+        .map(u64::from_str)
+        .transpose()?
+        .map(Duration::from_secs);
+    let enable_watchdog = matches.is_present("watchdog");
+    let no_http = matches.is_present("no_http");
+    let check = matches.is_present("check");
+    let poll_org_runners = matches.is_present("poll_org_runners");
+    let collectors = non_empty_values::<Collector>(&matches, "collectors")?
+        .into_iter()
+        .collect::<HashSet<_>>();
 
-    tracing_subscriber::fmt()
-        .json()
-        .with_max_level(Level::INFO)
-        .with_current_span(false)
-        .init();
+    // --log-level wins over RUST_LOG, which wins over the INFO default.
+    let log_filter = match matches.value_of("log_level") {
+        Some(directives) => EnvFilter::try_new(directives)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let subscriber = tracing_subscriber::fmt().with_env_filter(log_filter);
+
+    match matches.value_of("log_format") {
+        Some("pretty") => subscriber.pretty().init(),
+        Some("compact") => subscriber.compact().init(),
+        _ => subscriber.json().with_current_span(false).init(),
+    }
+
+    let client_config = github::ClientConfig {
+        base_url: github_base_url.map(String::from),
+        headers: api_headers,
+    };
 
+    for owner_token in &github_token_map {
+        client_config.initialise_owner(&owner_token.owner, owner_token.token.clone())?;
+    }
+
+    if let Some(app) = github_app {
+        let token = app.installation_token(&client_config).await?;
+        client_config.initialise(token.token)?;
+
+        tokio::spawn(github::watch_installation_token(
+            client_config,
+            app,
+            token.expires_at,
+        ));
+    } else if let Some(path) = github_token_file {
+        let github_token = github::read_token_file(&path).await?;
+        client_config.initialise(github_token.clone())?;
+
+        tokio::spawn(github::watch_token_file(
+            client_config,
+            path,
+            github_token,
+            github_token_reload_interval,
+        ));
+    } else if let Some(github_token) = matches
+        .value_of("github_token")
+        .map(String::from)
+        .or_else(|| config.token.clone())
     {
-        let mut builder = Octocrab::builder().personal_token(github_token);
+        client_config.initialise(github_token)?;
+    } else if github_token_map.is_empty() {
+        return Err(anyhow!(
+            "no GitHub token given on the command line or in the config file"
+        ));
+    }
 
-        if let Some(s) = github_base_url {
-            builder = builder.base_url(s)?;
-        }
+    github::set_retry_policy(retry_policy);
+    github::set_request_timeout(github_timeout);
+
+    if let Some(instance_id) = instance_id {
+        metrics::add_const_label("instance_id", instance_id);
+    }
+
+    for label in &static_labels {
+        metrics::add_const_label(&label.name, &label.value);
+    }
 
-        octocrab::initialise(builder)?;
+    if check {
+        let passed = check::run(check::Targets {
+            repos: &github_repos,
+            orgs: &github_orgs.load_full(),
+            users: &github_users,
+            billing: !github_enterprise,
+        })
+        .await;
+
+        return if passed {
+            Ok(())
+        } else {
+            Err(anyhow!("configuration check failed"))
+        };
+    }
+
+    metrics::build_info(crate_version!());
+
+    if let Some(max_age) = metric_max_age {
+        expiry::enable(max_age);
     }
 
     info!("configured repos: {:?}", github_repos);
     info!("configured organisations: {:?}", github_orgs);
+    info!("configured users: {:?}", github_users);
 
-    let github_workflows = Arc::new(
-        github_repos
-            .into_iter()
-            .map(|r| (r, RwLock::new(Vec::<Workflow>::new())))
-            .collect::<HashMap<_, _>>(),
-    );
-
-    let _ = tokio::spawn(tasks::poll_workflows(
-        github_workflows.clone(),
-        workflows_refresh_interval,
-    ));
-
-    let _ = tokio::spawn(tasks::poll_billable_ms(
-        github_workflows.clone(),
+    // Repositories and organisations may also come later, from discovery or a
+    // reloaded config file.
+    let has_orgs = !github_orgs.load().is_empty() || (config_file.is_some() && orgs_from_config);
+    let has_repos = !github_repos.is_empty()
+        || (discover_repos && has_orgs)
+        || (config_file.is_some() && repos_from_config);
+    let has_users = !github_users.is_empty() && !github_enterprise;
+
+    if !has_repos && !has_orgs && !has_users {
+        warn!("no repositories, organisations or users configured, no GitHub metrics will be exported");
+    }
+
+    let configured_repos = github_repos.iter().cloned().collect::<HashSet<_>>();
+    let github_workflows = Repositories::new(github_repos);
+
+    if let Some(path) = config_file {
+        let reload = config::Reload {
+            repos: Some(github_workflows.clone()).filter(|_| repos_from_config),
+            orgs: Some(github_orgs.clone()).filter(|_| orgs_from_config),
+        };
+
+        tokio::spawn(config::watch(path, config_reload_interval, config, reload));
+    }
+
+    pause::set_paused(false);
+
+    let (shutdown_sender, shutdown) = shutdown::channel();
+
+    tokio::spawn(async move {
+        match shutdown::signal().await {
+            Ok(()) => {
+                info!("received termination signal, shutting down");
+                let _ = shutdown_sender.send(true);
+            }
+            Err(err) => error!("failed to install signal handlers: {}", err),
+        }
+    });
+
+    let mut handles = Vec::new();
+
+    if discover_repos {
+        handles.push(tokio::spawn(tasks::poll_org_repos(
+            github_orgs.clone(),
+            github_workflows.clone(),
+            tasks::OrgReposOptions {
+                include_archived,
+                configured: configured_repos,
+                include: repo_include,
+                exclude: repo_exclude,
+            },
+            workflows_refresh_interval,
+            shutdown.clone(),
+        )));
+    }
+
+    if has_repos {
+        handles.push(tokio::spawn(tasks::poll_workflows(
+            github_workflows.clone(),
+            tasks::WorkflowsOptions {
+                max_workflows: max_workflows_per_repo,
+                include: workflow_include,
+                exclude: workflow_exclude,
+                concurrency: poll_concurrency,
+            },
+            workflows_refresh_interval,
+            shutdown.clone(),
+        )));
+
+        handles.push(tokio::spawn(tasks::poll_billable_ms(
+            github_workflows.clone(),
+            tasks::BillableMsOptions {
+                unit: billable_unit,
+                omit_zeros,
+                poll_divisor: billable_poll_divisor,
+                exclude_workflows: billable_exclude_workflows,
+                concurrency: poll_concurrency,
+            },
+            Rotation::new(poll_fraction),
+            timing_poll_interval,
+            shutdown.clone(),
+        )));
+
+        if collectors.contains(&Collector::CacheUsage) {
+            handles.push(tokio::spawn(tasks::poll_cache_usage(
+                github_workflows.clone(),
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::Collaborators) {
+            handles.push(tokio::spawn(tasks::poll_collaborators(
+                github_workflows.clone(),
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::Contributors) {
+            handles.push(tokio::spawn(tasks::poll_contributors(
+                github_workflows.clone(),
+                Rotation::new(poll_fraction),
+                contributors_poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::QueuedRuns) {
+            handles.push(tokio::spawn(tasks::poll_queued_runs(
+                github_workflows.clone(),
+                pending_threshold,
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::Repos) {
+            handles.push(tokio::spawn(tasks::poll_repos(
+                github_workflows.clone(),
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::ScheduleDrift) {
+            handles.push(tokio::spawn(tasks::poll_schedule_drift(
+                github_workflows.clone(),
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::SelfhostedJobs) {
+            handles.push(tokio::spawn(tasks::poll_selfhosted_jobs(
+                github_workflows.clone(),
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::Deployments) {
+            handles.push(tokio::spawn(tasks::poll_deployments(
+                github_workflows.clone(),
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::WorkflowLastRun) {
+            handles.push(tokio::spawn(tasks::poll_workflow_last_run(
+                github_workflows.clone(),
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if collectors.contains(&Collector::WorkflowRuns) {
+            handles.push(tokio::spawn(tasks::poll_workflow_runs(
+                github_workflows.clone(),
+                run_duration_buckets,
+                Rotation::new(poll_fraction),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+    }
+
+    let poll_repo_runners = has_repos && collectors.contains(&Collector::Runners);
+
+    if poll_repo_runners || (poll_org_runners && has_orgs) {
+        handles.push(tokio::spawn(tasks::poll_runners(
+            Some(github_workflows.clone()).filter(|_| poll_repo_runners),
+            Some(github_orgs.clone()).filter(|_| poll_org_runners),
+            poll_interval,
+            shutdown.clone(),
+        )));
+    }
+
+    if has_orgs {
+        if collectors.contains(&Collector::Orgs) {
+            handles.push(tokio::spawn(tasks::poll_orgs(
+                github_orgs.clone(),
+                poll_interval,
+                shutdown.clone(),
+            )));
+        }
+
+        if !github_enterprise {
+            handles.push(tokio::spawn(tasks::poll_orgs_billing(
+                github_orgs,
+                omit_zeros,
+                cost_rates,
+                billing_poll_interval,
+                shutdown.clone(),
+            )));
+        }
+    }
+
+    if has_users {
+        handles.push(tokio::spawn(tasks::poll_users_billing(
+            github_users,
+            omit_zeros,
+            billing_poll_interval,
+            shutdown.clone(),
+        )));
+    }
+
+    handles.push(tokio::spawn(tasks::poll_series_per_family(
+        poll_interval,
+        shutdown.clone(),
+    )));
+
+    handles.push(tokio::spawn(tasks::poll_rate_limit(
         poll_interval,
-    ));
+        shutdown.clone(),
+    )));
+
+    ready::expect_tasks(handles.len());
+
+    if enable_watchdog {
+        tokio::spawn(watchdog::watch());
+    }
+
+    if metric_max_age.is_some() {
+        tokio::spawn(expiry::watch());
+    }
+
+    if let Some(endpoint) = otlp_endpoint {
+        tokio::spawn(otlp::push(endpoint, otlp_interval));
+    }
+
+    if no_http {
+        info!("HTTP listener disabled, running poll tasks only");
+
+        shutdown.clone().requested().await;
+    } else {
+        let options = http::Options {
+            snapshot_interval: metrics_snapshot_interval,
+            route_prefix,
+            admin_token,
+            relabel_rules,
+            tls,
+        };
 
-    let _ = tokio::spawn(tasks::poll_orgs_billing(github_orgs, poll_interval));
+        if warmup {
+            info!("warming up before serving metrics");
 
-    http::listen(&bind_to).await?;
+            if !ready::wait(warmup_timeout).await {
+                warn!(
+                    "warmup did not complete within {:?}, serving metrics anyway",
+                    warmup_timeout
+                );
+            }
+        }
+
+        http::listen(&bind_to, options, shutdown).await?;
+    }
+
+    // Poll tasks stop at their next sleep, a cycle in progress gets a little
+    // time to finish before the runtime drops it.
+    let stopped = time::timeout(SHUTDOWN_TIMEOUT, async {
+        for handle in handles {
+            handle.await?;
+        }
+
+        Ok::<_, JoinError>(())
+    })
+    .await;
+
+    match stopped {
+        Ok(result) => result?,
+        Err(_) => warn!(
+            "poll tasks did not stop within {:?}, exiting anyway",
+            SHUTDOWN_TIMEOUT
+        ),
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repos(args: &[&str]) -> Result<Vec<Repository>> {
+        let matches = App::new("test")
+            .arg(
+                Arg::with_name("github_repos")
+                    .long("github-repos")
+                    .multiple(true)
+                    .use_delimiter(true)
+                    .default_value(""),
+            )
+            .get_matches_from(std::iter::once("test").chain(args.iter().copied()));
+
+        non_empty_values(&matches, "github_repos")
+    }
+
+    #[test]
+    fn leaves_out_the_empty_default() {
+        assert!(repos(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn leaves_out_blank_entries() {
+        assert!(repos(&["--github-repos", " , "]).unwrap().is_empty());
+        assert!(repos(&["--github-repos", ","]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_entries_around_stray_delimiters() {
+        assert_eq!(
+            repos(&["--github-repos", "a/b,, c/d ,"]).unwrap(),
+            vec![
+                "a/b".parse::<Repository>().unwrap(),
+                "c/d".parse::<Repository>().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert!(repos(&["--github-repos", "a/b,nope"]).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{expiry, ready, types::Repository, watchdog};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use prometheus::{
+    proto::{LabelPair, MetricFamily},
+    register_gauge_vec, register_int_counter_vec, register_int_gauge_vec, GaugeVec, IntCounterVec,
+    IntGaugeVec,
+};
+use std::{sync::RwLock, time::Instant};
+use tokio::time::error::Elapsed;
+
+/// Label names the exposition formats add to histogram and summary series,
+/// which a constant label can't take.
+pub const RESERVED_LABELS: &[&str] = &["le", "quantile"];
+
+/// Adds a label with a fixed value to every exported series.
+pub fn add_const_label(name: &str, value: &str) {
+    let mut label = LabelPair::new();
+    label.set_name(name.into());
+    label.set_value(value.into());
+
+    CONST_LABELS.write().unwrap().push(label);
+}
+
+/// Gathers all registered metrics, with the constant labels applied.
+pub fn gather() -> Vec<MetricFamily> {
+    let mut metric_families = prometheus::gather();
+    apply_const_labels(&mut metric_families, &CONST_LABELS.read().unwrap());
+
+    metric_families
+}
+
+/// Adds `const_labels` to every series, except those already carrying a label
+/// of the same name, whose own value wins.
+fn apply_const_labels(metric_families: &mut [MetricFamily], const_labels: &[LabelPair]) {
+    if const_labels.is_empty() {
+        return;
+    }
+
+    for mf in metric_families {
+        for m in mf.mut_metric().iter_mut() {
+            let labels = m.mut_label();
+
+            for label in const_labels {
+                if !labels.iter().any(|l| l.get_name() == label.get_name()) {
+                    labels.push(label.clone());
+                }
+            }
+
+            labels
+                .as_mut_slice()
+                .sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        }
+    }
+}
+
+/// Exports the running build, with `version` the crate version.
+pub fn build_info(version: &str) {
+    BUILD_INFO
+        .with_label_values(&[version, env!("RUSTC_VERSION"), env!("GIT_COMMIT")])
+        .set(1);
+}
+
+/// Sets the gauge for `labels` to `value`, or removes the series altogether
+/// when `value` is zero and `omit_zeros` is set.
+pub fn set_or_omit_zero(gauge: &'static GaugeVec, labels: &[&str], value: f64, omit_zeros: bool) {
+    if omit_zeros && value == 0.0 {
+        let _ = gauge.remove_label_values(labels);
+    } else {
+        gauge.with_label_values(labels).set(value);
+        expiry::touch(gauge, labels);
+    }
+}
+
+/// Records that an API call was skipped thanks to `reason`, e.g.
+/// `timing_sample`.
+pub fn api_call_saved(reason: &str) {
+    API_CALLS_SAVED.with_label_values(&[reason]).inc();
+}
+
+/// Records that `task` went through all of its work for one poll interval,
+/// in a cycle that began at `started`.
+pub fn poll_cycle_completed(task: &str, started: Instant) {
+    POLL_CYCLES.with_label_values(&[task]).inc();
+    ready::task_completed(task);
+    POLL_CYCLE_DURATION
+        .with_label_values(&[task])
+        .set(started.elapsed().as_secs_f64());
+}
+
+/// Records that a poll of `task`, e.g. of one repository, succeeded.
+pub fn poll_succeeded(task: &str) {
+    ready::poll_succeeded(task);
+    watchdog::progress(task);
+    LAST_SUCCESS_TIMESTAMP
+        .with_label_values(&[task])
+        .set(Utc::now().timestamp() as f64);
+}
+
+/// Records that a poll of `task` failed with `err`.
+pub fn poll_failed(task: &str, err: &anyhow::Error) {
+    ready::poll_failed(task);
+    watchdog::progress(task);
+    LAST_ERROR_TIMESTAMP
+        .with_label_values(&[task])
+        .set(Utc::now().timestamp() as f64);
+    POLL_ERRORS
+        .with_label_values(&[task, error_reason(err)])
+        .inc();
+}
+
+/// Coarse reason for `err`, from the first error in its chain that can be
+/// told apart.
+fn error_reason(err: &anyhow::Error) -> &'static str {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<octocrab::Error>() {
+            match err {
+                octocrab::Error::GitHub { .. } => return "http_status",
+                octocrab::Error::Serde { .. } | octocrab::Error::Json { .. } => return "decode",
+                _ => {}
+            }
+        } else if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            if err.is_timeout() {
+                return "timeout";
+            } else if err.is_decode() {
+                return "decode";
+            } else if err.is_status() {
+                return "http_status";
+            }
+        } else if cause.is::<Elapsed>() {
+            return "timeout";
+        } else if cause.is::<serde_json::Error>() {
+            return "decode";
+        }
+    }
+
+    "other"
+}
+
+/// Records the moment `repo` joined the set of polled repositories.
+pub fn repo_first_seen(repo: &Repository) {
+    REPO_FIRST_SEEN_TIMESTAMP
+        .with_label_values(&[&repo.owner, &repo.name])
+        .set(Utc::now().timestamp() as f64);
+}
+
+lazy_static! {
+    static ref CONST_LABELS: RwLock<Vec<LabelPair>> = RwLock::new(Vec::new());
+    pub static ref BUILD_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "github_exporter_build_info",
+        "Always 1, labelled with the version, rustc version and git commit of the running build",
+        &["version", "rustc", "git_commit"]
+    )
+    .unwrap();
+    pub static ref REPO_FIRST_SEEN_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "github_exporter_repo_first_seen_timestamp_seconds",
+        "Unix time at which the repository was first added to the polled set",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref POLL_CYCLE_DURATION: GaugeVec = register_gauge_vec!(
+        "github_exporter_poll_cycle_duration_seconds",
+        "How long the last completed polling cycle of each task took",
+        &["task"]
+    )
+    .unwrap();
+    pub static ref LAST_SUCCESS_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "github_exporter_last_success_timestamp_seconds",
+        "Unix time at which a poll of the task last succeeded",
+        &["task"]
+    )
+    .unwrap();
+    pub static ref LAST_ERROR_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "github_exporter_last_error_timestamp_seconds",
+        "Unix time at which a poll of the task last failed",
+        &["task"]
+    )
+    .unwrap();
+    pub static ref POLL_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "github_exporter_poll_errors_total",
+        "Number of failed polls per task, by coarse reason",
+        &["task", "reason"]
+    )
+    .unwrap();
+    pub static ref POLL_CYCLES: IntCounterVec = register_int_counter_vec!(
+        "github_exporter_poll_cycles_total",
+        "Number of completed polling cycles per task",
+        &["task"]
+    )
+    .unwrap();
+    pub static ref API_CALLS_SAVED: IntCounterVec = register_int_counter_vec!(
+        "github_exporter_api_calls_saved_total",
+        "Estimated number of Github API calls avoided, by reason",
+        &["reason"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::proto::Metric;
+
+    fn label(name: &str, value: &str) -> LabelPair {
+        let mut label = LabelPair::new();
+        label.set_name(name.into());
+        label.set_value(value.into());
+        label
+    }
+
+    fn family(labels: Vec<LabelPair>) -> MetricFamily {
+        let mut m = Metric::new();
+        m.set_label(labels.into());
+
+        let mut mf = MetricFamily::new();
+        mf.set_metric(vec![m].into());
+        mf
+    }
+
+    fn labels(mf: &MetricFamily) -> Vec<(&str, &str)> {
+        mf.get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|l| (l.get_name(), l.get_value()))
+            .collect()
+    }
+
+    #[test]
+    fn const_labels_leave_a_series_own_label_alone() {
+        let mut metric_families = vec![
+            family(vec![label("environment", "github-pages")]),
+            family(vec![label("repository", "a/b")]),
+        ];
+
+        apply_const_labels(
+            &mut metric_families,
+            &[label("environment", "prod"), label("team", "ci")],
+        );
+
+        assert_eq!(
+            labels(&metric_families[0]),
+            vec![("environment", "github-pages"), ("team", "ci")]
+        );
+        assert_eq!(
+            labels(&metric_families[1]),
+            vec![
+                ("environment", "prod"),
+                ("repository", "a/b"),
+                ("team", "ci")
+            ]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use crate::metrics;
+use anyhow::{bail, Result};
+use hyper::{header::CONTENT_TYPE, Body, Client, Method, Request, Uri};
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+use tracing::{error, info};
+
+/// OTLP `AGGREGATION_TEMPORALITY_CUMULATIVE`.
+const CUMULATIVE: u8 = 2;
+
+/// Periodically pushes all registered metrics to an OTLP/HTTP collector
+/// endpoint (e.g. `http://collector:4318/v1/metrics`), JSON-encoded.
+pub async fn push(endpoint: Uri, interval: Duration) {
+    let client = Client::new();
+    let start_time = unix_nanos();
+
+    loop {
+        time::sleep(interval).await;
+
+        let payload = encode(&metrics::gather(), &start_time);
+
+        match send(&client, &endpoint, payload).await {
+            Ok(()) => info!("pushed metrics to OTLP endpoint {}", endpoint),
+            Err(err) => error!(
+                "failed to push metrics to OTLP endpoint {}: {}",
+                endpoint, err
+            ),
+        }
+    }
+}
+
+async fn send(
+    client: &Client<hyper::client::HttpConnector>,
+    endpoint: &Uri,
+    payload: Value,
+) -> Result<()> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(endpoint.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&payload)?))?;
+
+    let response = client.request(request).await?;
+
+    if !response.status().is_success() {
+        bail!("collector responded with {}", response.status());
+    }
+
+    Ok(())
+}
+
+fn encode(metric_families: &[MetricFamily], start_time: &str) -> Value {
+    let now = unix_nanos();
+
+    let metrics = metric_families
+        .iter()
+        .map(|mf| {
+            let data_points = |point: &dyn Fn(&Metric) -> Value| {
+                mf.get_metric()
+                    .iter()
+                    .map(|m| {
+                        let mut value = point(m);
+                        value["attributes"] = attributes(m.get_label());
+                        value["timeUnixNano"] = now.clone().into();
+                        value
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let mut metric = match mf.get_field_type() {
+                MetricType::COUNTER => json!({
+                    "sum": {
+                        "dataPoints": data_points(&|m| json!({
+                            "startTimeUnixNano": start_time,
+                            "asDouble": m.get_counter().get_value(),
+                        })),
+                        "aggregationTemporality": CUMULATIVE,
+                        "isMonotonic": true,
+                    }
+                }),
+                MetricType::GAUGE => json!({
+                    "gauge": {
+                        "dataPoints": data_points(&|m| json!({
+                            "asDouble": m.get_gauge().get_value(),
+                        })),
+                    }
+                }),
+                MetricType::UNTYPED => json!({
+                    "gauge": {
+                        "dataPoints": data_points(&|m| json!({
+                            "asDouble": m.get_untyped().get_value(),
+                        })),
+                    }
+                }),
+                MetricType::HISTOGRAM => json!({
+                    "histogram": {
+                        "dataPoints": data_points(&|m| {
+                            let h = m.get_histogram();
+
+                            // OTLP bucket counts are per bucket, Prometheus' are cumulative.
+                            let mut previous = 0;
+                            let mut bucket_counts = h
+                                .get_bucket()
+                                .iter()
+                                .map(|b| {
+                                    let count = b.get_cumulative_count() - previous;
+                                    previous = b.get_cumulative_count();
+                                    count.to_string()
+                                })
+                                .collect::<Vec<_>>();
+                            bucket_counts.push((h.get_sample_count() - previous).to_string());
+
+                            json!({
+                                "startTimeUnixNano": start_time,
+                                "count": h.get_sample_count().to_string(),
+                                "sum": h.get_sample_sum(),
+                                "bucketCounts": bucket_counts,
+                                "explicitBounds": h
+                                    .get_bucket()
+                                    .iter()
+                                    .map(|b| b.get_upper_bound())
+                                    .collect::<Vec<_>>(),
+                            })
+                        }),
+                        "aggregationTemporality": CUMULATIVE,
+                    }
+                }),
+                MetricType::SUMMARY => json!({
+                    "summary": {
+                        "dataPoints": data_points(&|m| {
+                            let s = m.get_summary();
+
+                            json!({
+                                "startTimeUnixNano": start_time,
+                                "count": s.get_sample_count().to_string(),
+                                "sum": s.get_sample_sum(),
+                                "quantileValues": s
+                                    .get_quantile()
+                                    .iter()
+                                    .map(|q| json!({
+                                        "quantile": q.get_quantile(),
+                                        "value": q.get_value(),
+                                    }))
+                                    .collect::<Vec<_>>(),
+                            })
+                        }),
+                    }
+                }),
+            };
+
+            metric["name"] = mf.get_name().into();
+            metric["description"] = mf.get_help().into();
+            metric
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": env!("CARGO_PKG_NAME") },
+                }],
+            },
+            "scopeMetrics": [{
+                "scope": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+fn attributes(labels: &[LabelPair]) -> Value {
+    labels
+        .iter()
+        .map(|l| {
+            json!({
+                "key": l.get_name(),
+                "value": { "stringValue": l.get_value() },
+            })
+        })
+        .collect()
+}
+
+/// Nanoseconds since the Unix epoch, as a string since OTLP/JSON encodes
+/// 64-bit integers that way.
+fn unix_nanos() -> String {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before Unix epoch")
+        .as_nanos()
+        .to_string()
+}
@@ -0,0 +1,24 @@
+use lazy_static::lazy_static;
+use prometheus::{register_int_gauge, IntGauge};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Whether poll tasks should skip their work, e.g. while GitHub recovers from
+/// an incident. Metrics keep serving the last polled values meanwhile.
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+    PAUSED_GAUGE.set(paused as i64);
+}
+
+lazy_static! {
+    pub static ref PAUSED_GAUGE: IntGauge = register_int_gauge!(
+        "github_exporter_paused",
+        "Whether polling the Github API is paused"
+    )
+    .unwrap();
+}
@@ -0,0 +1,84 @@
+use lazy_static::lazy_static;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::time;
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(500);
+
+static EXPECTED_TASKS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Sets how many poll tasks must complete a cycle before the exporter is
+/// ready. Until then it never is.
+pub fn expect_tasks(count: usize) {
+    EXPECTED_TASKS.store(count, Ordering::Relaxed);
+}
+
+/// Records that a poll of `task` succeeded in its current cycle.
+pub fn poll_succeeded(task: &str) {
+    CYCLE_POLLS
+        .lock()
+        .unwrap()
+        .entry(task.to_string())
+        .or_default()
+        .succeeded = true;
+}
+
+/// Records that a poll of `task` failed in its current cycle.
+pub fn poll_failed(task: &str) {
+    CYCLE_POLLS
+        .lock()
+        .unwrap()
+        .entry(task.to_string())
+        .or_default()
+        .failed = true;
+}
+
+/// Ends the current cycle of `task`. It only counts towards readiness if at
+/// least one of its polls succeeded, or if it had nothing to poll at all: a
+/// cycle in which every poll failed, e.g. with a revoked token, doesn't.
+pub fn task_completed(task: &str) {
+    let polls = CYCLE_POLLS.lock().unwrap().remove(task).unwrap_or_default();
+
+    if polls.succeeded || !polls.failed {
+        let mut completed = COMPLETED_TASKS.lock().unwrap();
+
+        if !completed.contains(task) {
+            completed.insert(task.to_string());
+        }
+    }
+}
+
+/// Whether every poll task completed at least one successful cycle, so that
+/// metrics aren't served half empty.
+pub fn is_ready() -> bool {
+    COMPLETED_TASKS.lock().unwrap().len() >= EXPECTED_TASKS.load(Ordering::Relaxed)
+}
+
+/// Waits until ready, for at most `timeout`. Returns whether it got ready.
+pub async fn wait(timeout: Duration) -> bool {
+    let ready = async {
+        while !is_ready() {
+            time::sleep(WAIT_INTERVAL).await;
+        }
+    };
+
+    time::timeout(timeout, ready).await.is_ok()
+}
+
+/// How the polls of a task went so far in its current cycle.
+#[derive(Default)]
+struct CyclePolls {
+    succeeded: bool,
+    failed: bool,
+}
+
+lazy_static! {
+    static ref CYCLE_POLLS: Mutex<HashMap<String, CyclePolls>> = Mutex::new(HashMap::new());
+    static ref COMPLETED_TASKS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
@@ -0,0 +1,76 @@
+use crate::{
+    metrics,
+    types::{Repository, Workflow},
+};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
+
+/// Polled repositories and their cached workflows, shared between tasks.
+/// Repositories can be added and removed at runtime, e.g. by organisation
+/// discovery, so tasks work on snapshots rather than holding the lock.
+#[derive(Debug, Clone, Default)]
+pub struct Repositories {
+    inner: Arc<RwLock<HashMap<Repository, Vec<Workflow>>>>,
+}
+
+impl Repositories {
+    pub fn new(repos: impl IntoIterator<Item = Repository>) -> Self {
+        let repos = repos
+            .into_iter()
+            .map(|repo| {
+                metrics::repo_first_seen(&repo);
+                (repo, Vec::new())
+            })
+            .collect();
+
+        Repositories {
+            inner: Arc::new(RwLock::new(repos)),
+        }
+    }
+
+    pub async fn list(&self) -> Vec<Repository> {
+        self.inner.read().await.keys().cloned().collect()
+    }
+
+    /// Workflows last found for `repo`, empty when the repository is unknown.
+    pub async fn workflows(&self, repo: &Repository) -> Vec<Workflow> {
+        self.inner
+            .read()
+            .await
+            .get(repo)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replaces the workflows of `repo`, returning the previous ones. Does
+    /// nothing for repositories removed in the meantime.
+    pub async fn set_workflows(
+        &self,
+        repo: &Repository,
+        workflows: Vec<Workflow>,
+    ) -> Option<Vec<Workflow>> {
+        self.inner
+            .write()
+            .await
+            .get_mut(repo)
+            .map(|w| std::mem::replace(w, workflows))
+    }
+
+    /// Adds `repo`, returning whether it wasn't polled yet.
+    pub async fn insert(&self, repo: Repository) -> bool {
+        let mut repos = self.inner.write().await;
+
+        if repos.contains_key(&repo) {
+            return false;
+        }
+
+        metrics::repo_first_seen(&repo);
+        repos.insert(repo, Vec::new());
+
+        true
+    }
+
+    pub async fn remove(&self, repo: &Repository) {
+        self.inner.write().await.remove(repo);
+    }
+}
@@ -0,0 +1,38 @@
+/// Selects a rotating window of items each poll cycle, so that large sets
+/// are covered over several cycles instead of all at once.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    fraction: f64,
+    cursor: usize,
+}
+
+impl Rotation {
+    /// `fraction` of the items, in `(0, 1]`, is selected on every cycle.
+    pub fn new(fraction: f64) -> Self {
+        Rotation {
+            fraction,
+            cursor: 0,
+        }
+    }
+
+    /// Returns this cycle's window of `items` and advances past it. Items are
+    /// sorted first so that the rotation is stable across cycles.
+    pub fn select<T: Ord>(&mut self, items: impl IntoIterator<Item = T>) -> Vec<T> {
+        let mut items = items.into_iter().collect::<Vec<_>>();
+        let len = items.len();
+
+        if len == 0 || self.fraction >= 1.0 {
+            return items;
+        }
+
+        items.sort();
+
+        let take = ((len as f64 * self.fraction).ceil() as usize).clamp(1, len);
+        let start = self.cursor % len;
+        self.cursor = (start + take) % len;
+
+        items.rotate_left(start);
+        items.truncate(take);
+        items
+    }
+}
@@ -0,0 +1,53 @@
+use std::{future, io, time::Duration};
+use tokio::{
+    signal::{
+        self,
+        unix::{self as unix_signal, SignalKind},
+    },
+    sync::watch,
+    time,
+};
+
+/// Handed to every poll task so that it stops at its next sleep once the
+/// process is asked to terminate.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Resolves once shutdown was requested.
+    pub async fn requested(&mut self) {
+        while !*self.receiver.borrow() {
+            // Without a sender shutdown can't be requested anymore.
+            if self.receiver.changed().await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Sleeps for `duration`, returning early with `true` when shutdown is
+    /// requested in the meantime.
+    pub async fn sleep(&mut self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = time::sleep(duration) => false,
+            _ = self.requested() => true,
+        }
+    }
+}
+
+pub fn channel() -> (watch::Sender<bool>, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+
+    (sender, Shutdown { receiver })
+}
+
+/// Resolves on the first SIGTERM or SIGINT.
+pub async fn signal() -> io::Result<()> {
+    let mut terminate = unix_signal::signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = terminate.recv() => Ok(()),
+        result = signal::ctrl_c() => result,
+    }
+}
@@ -1,7 +1,40 @@
 mod poll_billable_ms;
+mod poll_cache_usage;
+mod poll_collaborators;
+mod poll_contributors;
+mod poll_deployments;
+mod poll_org_repos;
+mod poll_orgs;
 mod poll_orgs_billing;
+mod poll_queued_runs;
+mod poll_rate_limit;
+mod poll_repos;
+mod poll_runners;
+mod poll_schedule_drift;
+mod poll_selfhosted_jobs;
+mod poll_series_per_family;
+mod poll_users_billing;
+mod poll_workflow_last_run;
+mod poll_workflow_runs;
 mod poll_workflows;
+mod runs;
 
-pub use poll_billable_ms::poll_billable_ms;
-pub use poll_orgs_billing::poll_orgs_billing;
-pub use poll_workflows::poll_workflows;
+pub use poll_billable_ms::{poll_billable_ms, BillableMsOptions};
+pub use poll_cache_usage::poll_cache_usage;
+pub use poll_collaborators::poll_collaborators;
+pub use poll_contributors::poll_contributors;
+pub use poll_deployments::poll_deployments;
+pub use poll_org_repos::{poll_org_repos, OrgReposOptions};
+pub use poll_orgs::poll_orgs;
+pub use poll_orgs_billing::{poll_orgs_billing, CostRates};
+pub use poll_queued_runs::poll_queued_runs;
+pub use poll_rate_limit::poll_rate_limit;
+pub use poll_repos::poll_repos;
+pub use poll_runners::poll_runners;
+pub use poll_schedule_drift::poll_schedule_drift;
+pub use poll_selfhosted_jobs::poll_selfhosted_jobs;
+pub use poll_series_per_family::poll_series_per_family;
+pub use poll_users_billing::poll_users_billing;
+pub use poll_workflow_last_run::poll_workflow_last_run;
+pub use poll_workflow_runs::poll_workflow_runs;
+pub use poll_workflows::{poll_workflows, WorkflowsOptions};
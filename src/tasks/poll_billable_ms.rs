@@ -1,75 +1,315 @@
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
 use lazy_static::lazy_static;
-use prometheus::{register_gauge_vec, GaugeVec};
+use octocrab::models::WorkflowId;
+use prometheus::{
+    register_counter_vec, register_gauge_vec, register_int_counter_vec, CounterVec, GaugeVec,
+    IntCounterVec,
+};
 use serde::Deserialize;
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::{sync::RwLock, time};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 use tracing::{error, info};
 
-use crate::types::{Repository, Workflow, MACOS, UBUNTU, WINDOWS};
+use crate::{
+    expiry, github, metrics, pause,
+    repositories::Repositories,
+    rotation::Rotation,
+    shutdown::Shutdown,
+    types::{BillableUnit, Repository, Workflow, WorkflowPattern, MACOS, UBUNTU, WINDOWS},
+    watchdog,
+};
+
+#[derive(Debug, Clone)]
+pub struct BillableMsOptions {
+    pub unit: BillableUnit,
+    /// Remove series whose billable time is zero instead of exporting zeros.
+    pub omit_zeros: bool,
+    /// Poll each workflow only every `poll_divisor` times it comes up,
+    /// staggered by id so that each cycle polls a different share of them.
+    pub poll_divisor: u64,
+    /// Workflows whose billable time is never polled.
+    pub exclude_workflows: Vec<WorkflowPattern>,
+    /// Number of workflows polled at the same time.
+    pub concurrency: usize,
+}
 
 pub async fn poll_billable_ms(
-    github_workflows: Arc<HashMap<Repository, RwLock<Vec<Workflow>>>>,
+    github_workflows: Repositories,
+    options: BillableMsOptions,
+    mut rotation: Rotation,
     sleep: Duration,
+    mut shutdown: Shutdown,
 ) {
+    let run_counts = Mutex::new(RunCounts::new());
+    let mut turns = Turns::new();
+
     loop {
-        for (repo, workflows) in github_workflows.iter() {
-            for workflow in workflows.read().await.iter() {
-                if let Err(err) = poll_billable_ms_for_workflow(repo, workflow).await {
-                    error!(
-                        "failed to poll billable time for workflow {:?} in repo {}: {}",
-                        workflow, repo, err
-                    );
-                } else {
-                    info!("polled usage for {}:{}", repo, workflow.name);
+        watchdog::beat("billable_ms", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            let mut selected = Vec::new();
+
+            let repos = github_workflows.list().await;
+            let mut current = HashMap::new();
+
+            for repo in &repos {
+                let workflows = github_workflows.workflows(repo).await;
+                current.insert(repo.clone(), workflows.iter().map(|w| w.id).collect());
+            }
+
+            forget_removed(&current, &mut turns, &mut run_counts.lock().unwrap());
+
+            for repo in rotation.select(repos) {
+                for workflow in github_workflows.workflows(&repo).await {
+                    if options
+                        .exclude_workflows
+                        .iter()
+                        .any(|pattern| pattern.matches(&repo, &workflow))
+                    {
+                        metrics::api_call_saved("excluded_workflow");
+                        continue;
+                    }
+
+                    if !is_due(&mut turns, &repo, &workflow, options.poll_divisor) {
+                        metrics::api_call_saved("timing_sample");
+                        continue;
+                    }
+
+                    selected.push((repo.clone(), workflow));
                 }
             }
+
+            stream::iter(&selected)
+                .for_each_concurrent(options.concurrency, |(repo, workflow)| {
+                    let options = &options;
+                    let run_counts = &run_counts;
+
+                    async move {
+                        if let Err(err) =
+                            poll_billable_ms_for_workflow(repo, workflow, options, run_counts).await
+                        {
+                            metrics::poll_failed("billable_ms", &err);
+                            error!(
+                                "failed to poll billable time for workflow {:?} in repo {}: {}",
+                                workflow, repo, err
+                            );
+                        } else {
+                            metrics::poll_succeeded("billable_ms");
+                            ACTIONS_BILLABLE_LAST_POLLED
+                                .with_label_values(&[&repo.owner, &repo.name, &workflow.name])
+                                .set(Utc::now().timestamp() as f64);
+                            expiry::touch(
+                                &*ACTIONS_BILLABLE_LAST_POLLED,
+                                &[&repo.owner, &repo.name, &workflow.name],
+                            );
+
+                            info!("polled usage for {}:{}", repo, workflow.name);
+                        }
+                    }
+                })
+                .await;
+
+            metrics::poll_cycle_completed("billable_ms", started);
         }
 
-        time::sleep(sleep).await;
+        if shutdown.sleep(sleep).await {
+            break;
+        }
     }
 }
 
 async fn poll_billable_ms_for_workflow(
     repo: &Repository,
     workflow: &Workflow,
+    options: &BillableMsOptions,
+    run_counts: &Mutex<RunCounts>,
 ) -> anyhow::Result<()> {
-    let octocrab = octocrab::instance();
-
-    let usage = octocrab
-        .get::<Usage, _, _>(
-            octocrab
-                .absolute_url(format!(
-                    "repos/{owner}/{repo}/actions/workflows/{workflow_id}/timing",
-                    owner = repo.owner,
-                    repo = repo.name,
-                    workflow_id = workflow.id,
-                ))
-                .expect("failed to generate absolute API url"),
-            None::<&()>,
-        )
-        .await?;
-
-    if let Some(BillableTime { total_ms, .. }) = usage.billable.ubuntu {
-        ACTIONS_BILLABLE_MS
-            .with_label_values(&[&repo.owner, &repo.name, &workflow.name, UBUNTU])
-            .set(total_ms);
+    let gauge = billable_gauge(options.unit);
+
+    let usage = github::get::<Usage, _>(
+        "workflow_timing",
+        format!(
+            "repos/{owner}/{repo}/actions/workflows/{workflow_id}/timing",
+            owner = repo.owner,
+            repo = repo.name,
+            workflow_id = workflow.id,
+        ),
+        None::<&()>,
+    )
+    .await?;
+
+    let timings = [
+        (UBUNTU, usage.billable.ubuntu),
+        (MACOS, usage.billable.macos),
+        (WINDOWS, usage.billable.windows),
+    ];
+
+    let billable_ms = timings
+        .iter()
+        .filter_map(|(_, timing)| timing.as_ref())
+        .map(|timing| timing.total_ms)
+        .sum::<f64>();
+
+    for (os, timing) in timings {
+        let total_ms = match timing {
+            Some(BillableTime { total_ms, .. }) => total_ms,
+            None => continue,
+        };
+
+        metrics::set_or_omit_zero(
+            gauge,
+            &[&repo.owner, &repo.name, &workflow.name, os],
+            options.unit.convert(total_ms),
+            options.omit_zeros,
+        );
+
+        let key = (repo.clone(), workflow.id, os);
+
+        let previous = BILLABLE_TOTALS.lock().unwrap().insert(key, total_ms);
+        let repo_total =
+            ACTIONS_REPO_BILLABLE_MS_TOTAL.with_label_values(&[&repo.owner, &repo.name, os]);
+
+        // The first observation of a workflow only serves as a baseline.
+        if let Some(previous) = previous {
+            // GitHub resets the timing counters, e.g. at the start of a billing
+            // cycle, everything since then is new usage.
+            if total_ms < previous {
+                ACTIONS_BILLABLE_MS_RESETS
+                    .with_label_values(&[&repo.owner, &repo.name, &workflow.name])
+                    .inc();
+
+                repo_total.inc_by(total_ms);
+            } else {
+                repo_total.inc_by(total_ms - previous);
+            }
+        }
     }
 
-    if let Some(BillableTime { total_ms, .. }) = usage.billable.macos {
-        ACTIONS_BILLABLE_MS
-            .with_label_values(&[&repo.owner, &repo.name, &workflow.name, MACOS])
-            .set(total_ms);
+    // Counted after the billable time is exported, so that failing to count
+    // doesn't hold it back.
+    if billable_ms == 0.0 {
+        if let Err(err) = count_zero_billable_runs(repo, workflow, run_counts).await {
+            metrics::poll_failed("billable_ms", &err);
+            error!(
+                "failed to count zero billable runs of workflow {:?} in repo {}: {}",
+                workflow, repo, err
+            );
+        }
+    } else {
+        // Runs while the workflow is billed aren't zero-billable, counting
+        // starts over from a new baseline once its billable time drops back
+        // to zero.
+        run_counts
+            .lock()
+            .unwrap()
+            .remove(&(repo.clone(), workflow.id));
     }
 
-    if let Some(BillableTime { total_ms, .. }) = usage.billable.windows {
-        ACTIONS_BILLABLE_MS
-            .with_label_values(&[&repo.owner, &repo.name, &workflow.name, WINDOWS])
-            .set(total_ms);
+    Ok(())
+}
+
+/// Runs that happened since the last poll while the workflow's billable time
+/// stayed at zero on every OS didn't consume any billable minutes.
+async fn count_zero_billable_runs(
+    repo: &Repository,
+    workflow: &Workflow,
+    run_counts: &Mutex<RunCounts>,
+) -> anyhow::Result<()> {
+    let runs = github::get::<WorkflowRuns, _>(
+        "workflow_runs",
+        format!(
+            "repos/{owner}/{repo}/actions/workflows/{workflow_id}/runs",
+            owner = repo.owner,
+            repo = repo.name,
+            workflow_id = workflow.id,
+        ),
+        Some(&[("per_page", "1")]),
+    )
+    .await?;
+
+    let previous = run_counts
+        .lock()
+        .unwrap()
+        .insert((repo.clone(), workflow.id), runs.total_count);
+
+    // The first observation only records a baseline.
+    if let Some(previous) = previous {
+        if runs.total_count > previous {
+            ACTIONS_ZERO_BILLABLE_RUNS
+                .with_label_values(&[&repo.owner, &repo.name, &workflow.name])
+                .inc_by(runs.total_count - previous);
+        }
     }
 
     Ok(())
 }
 
+/// Last observed billable milliseconds per repository, workflow and OS.
+type BillableTotals = HashMap<(Repository, WorkflowId, &'static str), f64>;
+
+/// Billable milliseconds on `os` summed over all polled workflows of
+/// repositories owned by `owner`.
+pub fn billable_ms_for_owner(owner: &str, os: &str) -> f64 {
+    BILLABLE_TOTALS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|((repo, _, workflow_os), _)| repo.owner == owner && *workflow_os == os)
+        .map(|(_, total_ms)| total_ms)
+        .sum()
+}
+
+/// Drops what was recorded about workflows no longer in `current`, the
+/// workflow ids of every polled repository, so that e.g. the reconciliation
+/// with the organisation's billing doesn't keep counting their totals.
+fn forget_removed(
+    current: &HashMap<Repository, HashSet<WorkflowId>>,
+    turns: &mut Turns,
+    run_counts: &mut RunCounts,
+) {
+    let is_current = |repo: &Repository, id: &WorkflowId| {
+        current.get(repo).map_or(false, |ids| ids.contains(id))
+    };
+
+    BILLABLE_TOTALS
+        .lock()
+        .unwrap()
+        .retain(|(repo, id, _), _| is_current(repo, id));
+    turns.retain(|(repo, id), _| is_current(repo, id));
+    run_counts.retain(|(repo, id), _| is_current(repo, id));
+}
+
+/// Number of cycles each workflow came up for polling in.
+type Turns = HashMap<(Repository, WorkflowId), u64>;
+
+/// Whether `workflow` is polled on this turn, which is every `divisor`-th time
+/// it comes up. Counting the workflow's own turns, offset by its id, rather
+/// than its position in the cycle keeps the rotation and the filters from
+/// skipping the same workflows every time.
+fn is_due(turns: &mut Turns, repo: &Repository, workflow: &Workflow, divisor: u64) -> bool {
+    let turn = turns.entry((repo.clone(), workflow.id)).or_insert(0);
+    let due = (*turn + workflow.id.0) % divisor == 0;
+    *turn += 1;
+
+    due
+}
+
+/// Last observed number of runs per repository and workflow.
+type RunCounts = HashMap<(Repository, WorkflowId), u64>;
+
+fn billable_gauge(unit: BillableUnit) -> &'static GaugeVec {
+    match unit {
+        BillableUnit::Milliseconds => &ACTIONS_BILLABLE_MS,
+        BillableUnit::Seconds => &ACTIONS_BILLABLE_SECONDS,
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Usage {
     pub billable: Billable,
@@ -92,11 +332,141 @@ pub struct BillableTime {
     pub total_ms: f64,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRuns {
+    pub total_count: u64,
+}
+
 lazy_static! {
+    static ref BILLABLE_TOTALS: Mutex<BillableTotals> = Mutex::new(BillableTotals::new());
     pub static ref ACTIONS_BILLABLE_MS: GaugeVec = register_gauge_vec!(
         "github_actions_billable_ms",
         "Github Actions billable milliseconds",
         &["owner", "repository", "workflow", "os"]
     )
     .unwrap();
+    pub static ref ACTIONS_BILLABLE_SECONDS: GaugeVec = register_gauge_vec!(
+        "github_actions_billable_seconds",
+        "Github Actions billable seconds",
+        &["owner", "repository", "workflow", "os"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_BILLABLE_LAST_POLLED: GaugeVec = register_gauge_vec!(
+        "github_actions_billable_last_polled_timestamp_seconds",
+        "When Github Actions billable time was last polled for the workflow",
+        &["owner", "repository", "workflow"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_REPO_BILLABLE_MS_TOTAL: CounterVec = register_counter_vec!(
+        "github_actions_repo_billable_ms_total",
+        "Github Actions billable milliseconds accumulated per repository across workflows and resets",
+        &["owner", "repository", "os"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_BILLABLE_MS_RESETS: IntCounterVec = register_int_counter_vec!(
+        "github_actions_billable_ms_resets_total",
+        "Number of times Github Actions billable milliseconds were observed decreasing",
+        &["owner", "repository", "workflow"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_ZERO_BILLABLE_RUNS: IntCounterVec = register_int_counter_vec!(
+        "github_actions_zero_billable_runs_total",
+        "Number of Github Actions workflow runs that consumed no billable time",
+        &["owner", "repository", "workflow"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflow(id: u64) -> Workflow {
+        Workflow {
+            id: WorkflowId(id),
+            name: format!("workflow {}", id),
+            path: format!(".github/workflows/{}.yml", id),
+            state: "active".into(),
+        }
+    }
+
+    fn repo(name: &str) -> Repository {
+        format!("owner/{}", name).parse().unwrap()
+    }
+
+    #[test]
+    fn forgets_removed_workflows() {
+        let (kept, removed) = (repo("kept"), repo("removed"));
+        let mut turns = Turns::new();
+        let mut run_counts = RunCounts::new();
+
+        for (repo, id) in [(&kept, 1), (&kept, 2), (&removed, 3)] {
+            BILLABLE_TOTALS
+                .lock()
+                .unwrap()
+                .insert((repo.clone(), WorkflowId(id), UBUNTU), 60_000.0);
+            turns.insert((repo.clone(), WorkflowId(id)), 1);
+            run_counts.insert((repo.clone(), WorkflowId(id)), 1);
+        }
+
+        let current = [(
+            kept.clone(),
+            [WorkflowId(1)].iter().copied().collect::<HashSet<_>>(),
+        )]
+        .iter()
+        .cloned()
+        .collect();
+        forget_removed(&current, &mut turns, &mut run_counts);
+
+        assert_eq!(
+            turns.keys().collect::<Vec<_>>(),
+            vec![&(kept.clone(), WorkflowId(1))]
+        );
+        assert_eq!(
+            run_counts.keys().collect::<Vec<_>>(),
+            vec![&(kept, WorkflowId(1))]
+        );
+        assert_eq!(billable_ms_for_owner("owner", UBUNTU), 60_000.0);
+    }
+
+    #[test]
+    fn staggers_workflows_across_cycles() {
+        let mut turns = Turns::new();
+        let repo = repo("a");
+        let workflows = [workflow(1), workflow(2), workflow(3), workflow(4)];
+
+        for _ in 0..4 {
+            let due = workflows
+                .iter()
+                .filter(|workflow| is_due(&mut turns, &repo, workflow, 2))
+                .count();
+
+            assert_eq!(due, 2);
+        }
+    }
+
+    #[test]
+    fn polls_every_workflow_under_rotation() {
+        let mut turns = Turns::new();
+        let mut rotation = Rotation::new(0.5);
+        // Both ids even, one workflow per repository: each repository comes
+        // up every other cycle, always on the same parity.
+        let workflows = [(repo("a"), workflow(2)), (repo("b"), workflow(4))];
+        let mut polled = HashMap::new();
+
+        for _ in 0..8 {
+            let repos = workflows.iter().map(|(repo, _)| repo.clone());
+
+            for repo in rotation.select(repos) {
+                let (_, workflow) = workflows.iter().find(|(r, _)| *r == repo).unwrap();
+
+                if is_due(&mut turns, &repo, workflow, 2) {
+                    *polled.entry(repo).or_insert(0) += 1;
+                }
+            }
+        }
+
+        assert_eq!(polled.get(&repo("a")), Some(&2));
+        assert_eq!(polled.get(&repo("b")), Some(&2));
+    }
 }
@@ -0,0 +1,100 @@
+use crate::{
+    expiry, github, metrics, pause, repositories::Repositories, rotation::Rotation,
+    shutdown::Shutdown, types::Repository, watchdog,
+};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub async fn poll_cache_usage(
+    github_workflows: Repositories,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        watchdog::beat("cache_usage", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                match poll_cache_usage_for_repo(repo).await {
+                    Ok(true) => {
+                        metrics::poll_succeeded("cache_usage");
+                        info!("polled cache usage for {}", repo);
+                    }
+                    Ok(false) => {
+                        metrics::poll_succeeded("cache_usage");
+                        info!("cache usage for {} not found, skipping", repo);
+                    }
+                    Err(err) => {
+                        metrics::poll_failed("cache_usage", &err);
+                        error!("failed to poll cache usage for repo {}: {}", repo, err);
+                    }
+                }
+            }
+
+            metrics::poll_cycle_completed("cache_usage", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// Answers whether the repository's cache usage was found.
+async fn poll_cache_usage_for_repo(repo: &Repository) -> anyhow::Result<bool> {
+    let usage = match github::get_optional::<CacheUsage, _>(
+        "repo_cache_usage",
+        format!(
+            "repos/{owner}/{repo}/actions/cache/usage",
+            owner = repo.owner,
+            repo = repo.name,
+        ),
+        None::<&()>,
+        &[StatusCode::NOT_FOUND],
+    )
+    .await?
+    {
+        Some(usage) => usage,
+        None => return Ok(false),
+    };
+    let labels = [repo.owner.as_str(), repo.name.as_str()];
+
+    ACTIONS_CACHE_SIZE_BYTES
+        .with_label_values(&labels)
+        .set(usage.active_caches_size_in_bytes);
+    expiry::touch(&*ACTIONS_CACHE_SIZE_BYTES, &labels);
+    ACTIONS_CACHE_COUNT
+        .with_label_values(&labels)
+        .set(usage.active_caches_count);
+    expiry::touch(&*ACTIONS_CACHE_COUNT, &labels);
+
+    Ok(true)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CacheUsage {
+    pub active_caches_size_in_bytes: i64,
+    pub active_caches_count: i64,
+}
+
+lazy_static! {
+    pub static ref ACTIONS_CACHE_SIZE_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "github_actions_cache_size_bytes",
+        "Total size of the repository's active Github Actions caches",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_CACHE_COUNT: IntGaugeVec = register_int_gauge_vec!(
+        "github_actions_cache_count",
+        "Number of active Github Actions caches in the repository",
+        &["owner", "repository"]
+    )
+    .unwrap();
+}
@@ -0,0 +1,113 @@
+use crate::{
+    expiry, github, metrics, pause, repositories::Repositories, rotation::Rotation,
+    shutdown::Shutdown, types::Repository, watchdog,
+};
+use lazy_static::lazy_static;
+use octocrab::Page;
+use prometheus::{register_gauge_vec, GaugeVec};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub async fn poll_collaborators(
+    github_workflows: Repositories,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        watchdog::beat("collaborators", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                if let Err(err) = poll_collaborators_for_repo(repo).await {
+                    metrics::poll_failed("collaborators", &err);
+                    error!("failed to poll collaborators for repo {}: {}", repo, err);
+                } else {
+                    metrics::poll_succeeded("collaborators");
+                    info!("polled collaborators for {}", repo);
+                }
+            }
+
+            metrics::poll_cycle_completed("collaborators", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+async fn poll_collaborators_for_repo(repo: &Repository) -> anyhow::Result<()> {
+    let mut page = github::get::<Page<Collaborator>, _>(
+        "repo_collaborators",
+        format!(
+            "repos/{owner}/{repo}/collaborators",
+            owner = repo.owner,
+            repo = repo.name,
+        ),
+        Some(&[("per_page", "100")]),
+    )
+    .await?;
+
+    let (mut admin, mut write, mut read) = (0, 0, 0);
+
+    loop {
+        for collaborator in &page.items {
+            let permissions = &collaborator.permissions;
+
+            if permissions.admin {
+                admin += 1;
+            } else if permissions.push {
+                write += 1;
+            } else if permissions.pull {
+                read += 1;
+            }
+        }
+
+        match github::get_next_page("repo_collaborators", &repo.owner, &page).await? {
+            Some(next) => page = next,
+            None => break,
+        }
+    }
+
+    for (permission, count) in [(ADMIN, admin), (WRITE, write), (READ, read)] {
+        REPO_COLLABORATORS_COUNT
+            .with_label_values(&[&repo.owner, &repo.name, permission])
+            .set(f64::from(count));
+        expiry::touch(
+            &*REPO_COLLABORATORS_COUNT,
+            &[&repo.owner, &repo.name, permission],
+        );
+    }
+
+    Ok(())
+}
+
+static ADMIN: &str = "admin";
+static WRITE: &str = "write";
+static READ: &str = "read";
+
+#[derive(Debug, Deserialize)]
+pub struct Collaborator {
+    pub permissions: Permissions,
+}
+
+#[derive(Debug, Deserialize)]
+#[non_exhaustive]
+pub struct Permissions {
+    pub admin: bool,
+    pub push: bool,
+    pub pull: bool,
+}
+
+lazy_static! {
+    pub static ref REPO_COLLABORATORS_COUNT: GaugeVec = register_gauge_vec!(
+        "github_repo_collaborators_count",
+        "Github repository collaborators by permission level",
+        &["owner", "repository", "permission"]
+    )
+    .unwrap();
+}
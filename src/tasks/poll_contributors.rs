@@ -0,0 +1,97 @@
+use crate::{
+    expiry, github, metrics, pause, repositories::Repositories, rotation::Rotation,
+    shutdown::Shutdown, types::Repository, watchdog,
+};
+use lazy_static::lazy_static;
+use octocrab::Page;
+use prometheus::{register_gauge_vec, GaugeVec};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub async fn poll_contributors(
+    github_workflows: Repositories,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        watchdog::beat("contributors", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                match poll_contributors_for_repo(repo).await {
+                    Ok(true) => {
+                        metrics::poll_succeeded("contributors");
+                        info!("polled contributors for {}", repo);
+                    }
+                    Ok(false) => info!("contributors for {} not available yet", repo),
+                    Err(err) => {
+                        metrics::poll_failed("contributors", &err);
+                        error!("failed to poll contributors for repo {}: {}", repo, err);
+                    }
+                }
+            }
+
+            metrics::poll_cycle_completed("contributors", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// Counts contributors with a single request: with one contributor per page,
+/// the number of the last page is the number of contributors. Returns whether
+/// GitHub had the list ready.
+async fn poll_contributors_for_repo(repo: &Repository) -> anyhow::Result<bool> {
+    let page = github::get_stats::<Page<Contributor>, _>(
+        "repo_contributors",
+        format!(
+            "repos/{owner}/{repo}/contributors",
+            owner = repo.owner,
+            repo = repo.name,
+        ),
+        Some(&[("per_page", "1")]),
+    )
+    .await?;
+
+    let page = match page {
+        Some(page) => page,
+        None => return Ok(false),
+    };
+
+    let count = page
+        .last
+        .as_ref()
+        .and_then(|last| {
+            last.query_pairs()
+                .find(|(name, _)| name == "page")
+                .and_then(|(_, page)| page.parse::<f64>().ok())
+        })
+        .unwrap_or(page.items.len() as f64);
+
+    REPO_CONTRIBUTORS
+        .with_label_values(&[&repo.owner, &repo.name])
+        .set(count);
+    expiry::touch(&*REPO_CONTRIBUTORS, &[&repo.owner, &repo.name]);
+
+    Ok(true)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Contributor {
+    pub login: String,
+}
+
+lazy_static! {
+    pub static ref REPO_CONTRIBUTORS: GaugeVec = register_gauge_vec!(
+        "github_repo_contributors_count",
+        "Number of contributors to the Github repository",
+        &["owner", "repository"]
+    )
+    .unwrap();
+}
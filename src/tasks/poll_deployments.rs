@@ -0,0 +1,283 @@
+use crate::{
+    github, metrics, pause, repositories::Repositories, rotation::Rotation, shutdown::Shutdown,
+    types::Repository, watchdog,
+};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+static SUCCESS: &str = "success";
+static FINAL_STATES: &[&str] = &["failure", "error", "inactive"];
+
+pub async fn poll_deployments(
+    github_workflows: Repositories,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    let mut resolved = HashMap::new();
+
+    loop {
+        watchdog::beat("deployments", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                if let Err(err) = poll_deployments_for_repo(repo, &mut resolved).await {
+                    metrics::poll_failed("deployments", &err);
+                    error!("failed to poll deployments for repo {}: {}", repo, err);
+                } else {
+                    metrics::poll_succeeded("deployments");
+                    info!("polled deployments for {}", repo);
+                }
+            }
+
+            metrics::poll_cycle_completed("deployments", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// Ids of the deployments already counted, or that ended without succeeding,
+/// per repository.
+type ResolvedDeployments = HashMap<Repository, HashSet<u64>>;
+
+/// Counts deployments that succeeded since the previous poll. The first poll
+/// of a repository only records the deployments that already ended, so that
+/// historical ones aren't counted while those still running are once they
+/// succeed.
+async fn poll_deployments_for_repo(
+    repo: &Repository,
+    resolved: &mut ResolvedDeployments,
+) -> anyhow::Result<()> {
+    let deployments = github::get::<Vec<Deployment>, _>(
+        "deployments",
+        format!(
+            "repos/{owner}/{repo}/deployments",
+            owner = repo.owner,
+            repo = repo.name,
+        ),
+        Some(&[("per_page", "100")]),
+    )
+    .await?;
+
+    let resolved = match resolved.get_mut(repo) {
+        Some(resolved) => resolved,
+        None => {
+            let mut ended = HashSet::new();
+
+            for deployment in &deployments {
+                if has_ended(&get_statuses(repo, deployment.id).await?) {
+                    ended.insert(deployment.id);
+                }
+            }
+
+            resolved.insert(repo.clone(), ended);
+            return Ok(());
+        }
+    };
+
+    for deployment in &deployments {
+        if resolved.contains(&deployment.id) {
+            continue;
+        }
+
+        let statuses = get_statuses(repo, deployment.id).await?;
+
+        // Statuses are newest first, so this is when the deployment first succeeded.
+        if let Some(success) = statuses.iter().rev().find(|s| s.state == SUCCESS) {
+            let committed_at = get_commit_date(repo, &deployment.sha).await?;
+            let lead_time = (success.created_at - committed_at).num_seconds().max(0);
+
+            DEPLOYMENT_FREQUENCY
+                .with_label_values(&[&repo.owner, &repo.name, &deployment.environment])
+                .inc();
+            ACTIONS_LEAD_TIME_SECONDS
+                .with_label_values(&[&repo.owner, &repo.name])
+                .observe(lead_time as f64);
+
+            resolved.insert(deployment.id);
+        } else if has_ended(&statuses) {
+            resolved.insert(deployment.id);
+        }
+    }
+
+    // Only the latest page is ever looked at, older ids can be forgotten.
+    let current = deployments.iter().map(|d| d.id).collect::<HashSet<_>>();
+    resolved.retain(|id| current.contains(id));
+
+    Ok(())
+}
+
+/// Whether the deployment with `statuses`, newest first, won't change anymore.
+fn has_ended(statuses: &[Status]) -> bool {
+    statuses.first().map_or(false, |latest| {
+        latest.state == SUCCESS || FINAL_STATES.contains(&latest.state.as_str())
+    })
+}
+
+/// Statuses of a deployment, newest first.
+async fn get_statuses(repo: &Repository, deployment_id: u64) -> anyhow::Result<Vec<Status>> {
+    let statuses = github::get::<Vec<Status>, _>(
+        "deployment_statuses",
+        format!(
+            "repos/{owner}/{repo}/deployments/{deployment_id}/statuses",
+            owner = repo.owner,
+            repo = repo.name,
+            deployment_id = deployment_id,
+        ),
+        Some(&[("per_page", "100")]),
+    )
+    .await?;
+
+    Ok(statuses)
+}
+
+/// Committer date of `sha`.
+///
+/// Lead time is approximated as the time between the deployed commit and its
+/// deployment succeeding: earlier commits shipped by the same deployment are
+/// not looked at, so changes batched into one deployment appear faster than
+/// they were.
+async fn get_commit_date(repo: &Repository, sha: &str) -> anyhow::Result<DateTime<Utc>> {
+    let commit = github::get::<Commit, _>(
+        "commit",
+        format!(
+            "repos/{owner}/{repo}/commits/{sha}",
+            owner = repo.owner,
+            repo = repo.name,
+            sha = sha,
+        ),
+        None::<&()>,
+    )
+    .await?;
+
+    Ok(commit.commit.committer.date)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Deployment {
+    pub id: u64,
+    pub sha: String,
+    pub environment: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Status {
+    pub state: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Commit {
+    pub commit: CommitDetails,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommitDetails {
+    pub committer: Signature,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Signature {
+    pub date: DateTime<Utc>,
+}
+
+lazy_static! {
+    pub static ref DEPLOYMENT_FREQUENCY: IntCounterVec = register_int_counter_vec!(
+        "github_deployment_frequency_total",
+        "Number of successful Github deployments",
+        &["owner", "repository", "environment"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_LEAD_TIME_SECONDS: HistogramVec = register_histogram_vec!(
+        "github_actions_lead_time_seconds",
+        "Time between a deployed commit and its Github deployment succeeding",
+        &["owner", "repository"],
+        vec![300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0, 86400.0, 172800.0, 604800.0]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::testing::mock_github;
+    use hyper::{Body, Response};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[tokio::test]
+    async fn counts_deployment_running_at_startup_once_it_succeeds() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let statuses_polls = polls.clone();
+
+        mock_github("deploying", move |req| {
+            let polls = statuses_polls.clone();
+
+            async move {
+                let body = match req.uri().path() {
+                    "/repos/deploying/app/deployments" => {
+                        r#"[{"id": 1, "sha": "abc", "environment": "production"},
+                            {"id": 2, "sha": "def", "environment": "production"}]"#
+                    }
+                    // Already over before the first poll.
+                    "/repos/deploying/app/deployments/2/statuses" => {
+                        r#"[{"state": "success", "created_at": "2021-07-01T09:00:00Z"}]"#
+                    }
+                    "/repos/deploying/app/deployments/1/statuses" => {
+                        if polls.fetch_add(1, Ordering::SeqCst) == 0 {
+                            r#"[{"state": "in_progress", "created_at": "2021-07-01T10:00:00Z"}]"#
+                        } else {
+                            r#"[{"state": "success", "created_at": "2021-07-01T10:05:00Z"},
+                                {"state": "in_progress", "created_at": "2021-07-01T10:00:00Z"}]"#
+                        }
+                    }
+                    "/repos/deploying/app/commits/abc" => {
+                        r#"{"commit": {"committer": {"date": "2021-07-01T09:55:00Z"}}}"#
+                    }
+                    path => panic!("unexpected request for {}", path),
+                };
+
+                Response::new(Body::from(body))
+            }
+        });
+
+        let repo = "deploying/app".parse::<Repository>().unwrap();
+        let deployments =
+            DEPLOYMENT_FREQUENCY.with_label_values(&["deploying", "app", "production"]);
+        let mut resolved = ResolvedDeployments::new();
+
+        poll_deployments_for_repo(&repo, &mut resolved)
+            .await
+            .unwrap();
+        assert_eq!(deployments.get(), 0);
+        assert_eq!(resolved[&repo], [2].iter().copied().collect::<HashSet<_>>());
+
+        poll_deployments_for_repo(&repo, &mut resolved)
+            .await
+            .unwrap();
+        assert_eq!(deployments.get(), 1);
+        assert_eq!(
+            resolved[&repo],
+            [1, 2].iter().copied().collect::<HashSet<_>>()
+        );
+
+        poll_deployments_for_repo(&repo, &mut resolved)
+            .await
+            .unwrap();
+        assert_eq!(deployments.get(), 1);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    github, metrics, pause,
+    repositories::Repositories,
+    shutdown::Shutdown,
+    types::{NamePattern, Organisations, Repository},
+    watchdog,
+};
+use octocrab::Page;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+#[derive(Debug, Clone)]
+pub struct OrgReposOptions {
+    /// Also poll archived repositories.
+    pub include_archived: bool,
+    /// Repositories configured explicitly, never removed by discovery.
+    pub configured: HashSet<Repository>,
+    /// When non-empty, only discovered repositories whose `owner/repo` matches
+    /// one of these are polled.
+    pub include: Vec<NamePattern>,
+    /// Discovered repositories whose `owner/repo` matches one of these are
+    /// not polled, even if included.
+    pub exclude: Vec<NamePattern>,
+}
+
+impl OrgReposOptions {
+    fn selects(&self, repo: &Repository) -> bool {
+        let name = repo.to_string();
+
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(&name)))
+            && !self.exclude.iter().any(|p| p.matches(&name))
+    }
+}
+
+/// Keeps `github_workflows` in sync with the repositories of each
+/// organisation, adding new ones and dropping those that went away.
+pub async fn poll_org_repos(
+    orgs: Organisations,
+    github_workflows: Repositories,
+    options: OrgReposOptions,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    let mut discovered = HashMap::new();
+
+    loop {
+        watchdog::beat("org_repos", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            let orgs = orgs.load_full();
+
+            for org in orgs.iter() {
+                match list_org_repos(org, options.include_archived).await {
+                    Ok(repos) => {
+                        metrics::poll_succeeded("org_repos");
+
+                        let repos = repos
+                            .into_iter()
+                            .filter(|repo| options.selects(repo))
+                            .collect::<HashSet<_>>();
+
+                        let previous = discovered
+                            .insert(org.clone(), repos.clone())
+                            .unwrap_or_default();
+
+                        for repo in repos.difference(&previous) {
+                            if github_workflows.insert(repo.clone()).await {
+                                info!("discovered repo {}", repo);
+                            }
+                        }
+
+                        for repo in previous.difference(&repos) {
+                            if !options.configured.contains(repo) {
+                                info!("repo {} is gone from org `{}`", repo, org);
+                                github_workflows.remove(repo).await;
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        metrics::poll_failed("org_repos", &err);
+                        error!("failed to list repos for org `{}`: {}", org, err);
+                    }
+                }
+            }
+
+            // Organisations removed from the config take their repositories
+            // along.
+            for (org, repos) in &discovered {
+                if !orgs.contains(org) {
+                    for repo in repos.difference(&options.configured) {
+                        github_workflows.remove(repo).await;
+                    }
+                }
+            }
+            discovered.retain(|org, _| orgs.contains(org));
+
+            metrics::poll_cycle_completed("org_repos", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+async fn list_org_repos(org: &str, include_archived: bool) -> anyhow::Result<HashSet<Repository>> {
+    let mut page = github::get::<Page<OrgRepository>, _>(
+        "org_repos",
+        format!("orgs/{}/repos", org),
+        Some(&[("per_page", "100")]),
+    )
+    .await?;
+
+    let mut repos = HashSet::new();
+
+    loop {
+        for repo in &page.items {
+            if include_archived || !repo.archived {
+                repos.insert(Repository {
+                    owner: org.into(),
+                    name: repo.name.clone(),
+                });
+            }
+        }
+
+        match github::get_next_page("org_repos", org, &page).await? {
+            Some(next) => page = next,
+            None => break,
+        }
+    }
+
+    Ok(repos)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrgRepository {
+    pub name: String,
+    pub archived: bool,
+}
@@ -0,0 +1,171 @@
+use crate::{expiry, github, metrics, pause, shutdown::Shutdown, types::Organisations, watchdog};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, GaugeVec};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+pub async fn poll_orgs(orgs: Organisations, sleep: Duration, mut shutdown: Shutdown) {
+    let mut plans = HashMap::new();
+
+    loop {
+        watchdog::beat("orgs", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for org in orgs.load_full().iter() {
+                match poll_org(org, &mut plans).await {
+                    Ok(()) => metrics::poll_succeeded("orgs"),
+                    Err(err) => {
+                        metrics::poll_failed("orgs", &err);
+                        error!("failed to poll org `{}`: {}", org, err);
+                    }
+                }
+
+                if let Err(err) = poll_required_workflows(org).await {
+                    metrics::poll_failed("orgs", &err);
+                    error!(
+                        "failed to poll required workflows for org `{}`: {}",
+                        org, err
+                    );
+                }
+            }
+
+            metrics::poll_cycle_completed("orgs", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+async fn poll_org(org: &str, plans: &mut HashMap<String, String>) -> anyhow::Result<()> {
+    let organisation =
+        github::get::<OrganisationDetails, _>("org", format!("orgs/{}", org), None::<&()>).await?;
+
+    // The plan is only visible to organisation owners.
+    let plan = match organisation.plan {
+        Some(plan) => plan,
+        None => {
+            info!("no plan information visible for org `{}`", org);
+            return Ok(());
+        }
+    };
+
+    if let Some(previous) = plans.insert(org.into(), plan.name.clone()) {
+        if previous != plan.name {
+            let _ = ORG_PLAN.remove_label_values(&[org, &previous]);
+        }
+    }
+
+    ORG_PLAN.with_label_values(&[org, &plan.name]).set(1.0);
+    expiry::touch(&*ORG_PLAN, &[org, &plan.name]);
+
+    if let Some(filled_seats) = plan.filled_seats {
+        ORG_SEATS_FILLED.with_label_values(&[org]).set(filled_seats);
+        expiry::touch(&*ORG_SEATS_FILLED, &[org]);
+
+        if let Some(seats) = plan.seats {
+            ORG_SEATS_AVAILABLE
+                .with_label_values(&[org])
+                .set((seats - filled_seats).max(0.0));
+            expiry::touch(&*ORG_SEATS_AVAILABLE, &[org]);
+        }
+    }
+
+    info!("polled org `{}`", org);
+
+    Ok(())
+}
+
+/// Counts the organisation's required workflows by scope. Organisations
+/// without access to the feature are skipped.
+async fn poll_required_workflows(org: &str) -> anyhow::Result<()> {
+    let required_workflows = github::get_optional::<RequiredWorkflows, _>(
+        "org_required_workflows",
+        format!("orgs/{}/actions/required_workflows", org),
+        Some(&[("per_page", "100")]),
+        &[StatusCode::NOT_FOUND],
+    )
+    .await?;
+
+    let required_workflows = match required_workflows {
+        Some(required_workflows) => required_workflows,
+        None => return Ok(()),
+    };
+
+    for scope in [SCOPE_ALL, SCOPE_SELECTED] {
+        let count = required_workflows
+            .required_workflows
+            .iter()
+            .filter(|w| w.scope == scope)
+            .count();
+
+        ORG_REQUIRED_WORKFLOWS
+            .with_label_values(&[org, scope])
+            .set(count as f64);
+        expiry::touch(&*ORG_REQUIRED_WORKFLOWS, &[org, scope]);
+    }
+
+    Ok(())
+}
+
+static SCOPE_ALL: &str = "all";
+static SCOPE_SELECTED: &str = "selected";
+
+#[derive(Debug, Deserialize)]
+pub struct OrganisationDetails {
+    pub plan: Option<Plan>,
+}
+
+#[derive(Debug, Deserialize)]
+#[non_exhaustive]
+pub struct Plan {
+    pub name: String,
+    pub seats: Option<f64>,
+    pub filled_seats: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequiredWorkflows {
+    pub required_workflows: Vec<RequiredWorkflow>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequiredWorkflow {
+    /// Either `all` or `selected` repositories.
+    pub scope: String,
+}
+
+lazy_static! {
+    pub static ref ORG_PLAN: GaugeVec = register_gauge_vec!(
+        "github_org_plan",
+        "Github organisation plan",
+        &["organisation", "plan"]
+    )
+    .unwrap();
+    pub static ref ORG_SEATS_FILLED: GaugeVec = register_gauge_vec!(
+        "github_org_seats_filled",
+        "Github organisation filled seats",
+        &["organisation"]
+    )
+    .unwrap();
+    pub static ref ORG_SEATS_AVAILABLE: GaugeVec = register_gauge_vec!(
+        "github_org_seats_available",
+        "Github organisation seats still available in the plan",
+        &["organisation"]
+    )
+    .unwrap();
+    pub static ref ORG_REQUIRED_WORKFLOWS: GaugeVec = register_gauge_vec!(
+        "github_org_required_workflows_total",
+        "Number of Github organisation required workflows by repository scope",
+        &["organisation", "scope"]
+    )
+    .unwrap();
+}
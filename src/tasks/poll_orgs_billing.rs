@@ -1,115 +1,350 @@
-use crate::types::{Organisation, MACOS, UBUNTU, WINDOWS};
+use super::poll_billable_ms::billable_ms_for_owner;
+use crate::{
+    expiry, github,
+    metrics::{self, set_or_omit_zero},
+    pause,
+    shutdown::Shutdown,
+    types::{Organisations, MACOS, UBUNTU, WINDOWS},
+    watchdog,
+};
+use chrono::{Datelike, Duration as ChronoDuration, NaiveDate, TimeZone, Utc};
+use hyper::StatusCode;
 use lazy_static::lazy_static;
-use prometheus::{register_gauge_vec, GaugeVec};
+use prometheus::{register_gauge, register_gauge_vec, Gauge, GaugeVec};
 use serde::Deserialize;
 use serde_with::{serde_as, DisplayFromStr};
-use std::{sync::Arc, time::Duration};
-use tokio::time;
-use tracing::{error, info};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+/// USD rates estimated costs are computed with. These are estimates: they
+/// don't account for discounts, the included minutes of the plan nor the
+/// multipliers of larger runners.
+#[derive(Debug, Clone, Copy)]
+pub struct CostRates {
+    pub ubuntu_per_min: f64,
+    pub macos_per_min: f64,
+    pub windows_per_min: f64,
+    /// Per GB of paid shared storage for the month.
+    pub storage_per_gb: f64,
+    /// Per GB of paid Packages bandwidth.
+    pub bandwidth_per_gb: f64,
+}
+
+impl CostRates {
+    fn per_min(&self, os: &str) -> f64 {
+        match os {
+            UBUNTU => self.ubuntu_per_min,
+            MACOS => self.macos_per_min,
+            WINDOWS => self.windows_per_min,
+            _ => 0.0,
+        }
+    }
+}
+
+pub async fn poll_orgs_billing(
+    orgs: Organisations,
+    omit_zeros: bool,
+    rates: CostRates,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    let mut minutes_used = HashMap::new();
 
-pub async fn poll_orgs_billing(orgs: Arc<Vec<Organisation>>, sleep: Duration) {
     loop {
-        for org in orgs.iter() {
-            if let Err(err) = poll_org_billing(org).await {
-                error!("failed to poll org billing for org `{}`: {}", org, err);
+        watchdog::beat("orgs_billing", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            let orgs = orgs.load_full();
+
+            for org in orgs.iter() {
+                match poll_org_billing(org, omit_zeros, &rates).await {
+                    Ok(total_minutes_used) => {
+                        metrics::poll_succeeded("orgs_billing");
+                        minutes_used.insert(org.clone(), total_minutes_used);
+                    }
+                    Err(err) => {
+                        metrics::poll_failed("orgs_billing", &err);
+                        error!("failed to poll org billing for org `{}`: {}", org, err);
+                    }
+                }
             }
+
+            // Orgs that failed this cycle contribute their last known value, and
+            // the sum is only exported once every org has been polled once.
+            minutes_used.retain(|org, _| orgs.contains(org));
+
+            if !orgs.is_empty() && minutes_used.len() == orgs.len() {
+                BILLING_TOTAL_MINUTES_USED_ALL_ORGS.set(minutes_used.values().sum());
+            }
+
+            metrics::poll_cycle_completed("orgs_billing", started);
         }
 
-        time::sleep(sleep).await;
+        if shutdown.sleep(sleep).await {
+            break;
+        }
     }
 }
 
-async fn poll_org_billing(org: &str) -> anyhow::Result<()> {
-    let octocrab = octocrab::instance();
-
-    let actions_billing_fut = octocrab.get::<ActionsBilling, _, _>(
-        octocrab
-            .absolute_url(format!("orgs/{}/settings/billing/actions", org))
-            .expect("failed to generate absolute API url"),
+/// Polls the organisation's billing, returning its Actions total minutes used.
+async fn poll_org_billing(org: &str, omit_zeros: bool, rates: &CostRates) -> anyhow::Result<f64> {
+    let actions_billing_fut = github::get::<ActionsBilling, _>(
+        "org_billing_actions",
+        format!("orgs/{}/settings/billing/actions", org),
         None::<&()>,
     );
 
-    let packages_billing_fut = octocrab.get::<PackagesBilling, _, _>(
-        octocrab
-            .absolute_url(format!("orgs/{}/settings/billing/packages", org))
-            .expect("failed to generate absolute API url"),
+    let packages_billing_fut = github::get::<PackagesBilling, _>(
+        "org_billing_packages",
+        format!("orgs/{}/settings/billing/packages", org),
         None::<&()>,
     );
 
-    let shared_storage_billing_fut = octocrab.get::<SharedStorageBilling, _, _>(
-        octocrab
-            .absolute_url(format!("orgs/{}/settings/billing/shared-storage", org))
-            .expect("failed to generate absolute API url"),
+    let shared_storage_billing_fut = github::get::<SharedStorageBilling, _>(
+        "org_billing_shared_storage",
+        format!("orgs/{}/settings/billing/shared-storage", org),
         None::<&()>,
     );
 
-    let (actions_billing_res, packages_billing_res, shared_storage_billing_res) = tokio::join!(
+    let copilot_billing_fut = get_copilot_billing(org);
+
+    let (
+        actions_billing_res,
+        packages_billing_res,
+        shared_storage_billing_res,
+        copilot_billing_res,
+    ) = tokio::join!(
         actions_billing_fut,
         packages_billing_fut,
-        shared_storage_billing_fut
+        shared_storage_billing_fut,
+        copilot_billing_fut
     );
 
-    set_metrics_actions_billing(org, &actions_billing_res?);
-    set_metrics_packages_billing(org, &packages_billing_res?);
-    set_metrics_shared_storage_billing(org, &shared_storage_billing_res?);
+    let actions_billing = actions_billing_res?;
+    let packages_billing = packages_billing_res?;
+    let shared_storage_billing = shared_storage_billing_res?;
+    set_metrics_actions_billing(org, &actions_billing, omit_zeros);
+    set_metrics_packages_billing(org, &packages_billing, omit_zeros);
+    set_metrics_shared_storage_billing(org, &shared_storage_billing, omit_zeros);
+    set_metrics_estimated_cost(
+        org,
+        &actions_billing,
+        &packages_billing,
+        &shared_storage_billing,
+        rates,
+        omit_zeros,
+    );
+
+    // Copilot billing needs its own scope, which the token may well lack:
+    // that shouldn't fail the rest of the organisation's billing.
+    match copilot_billing_res {
+        Ok(Some(copilot_billing)) => set_metrics_copilot_billing(org, &copilot_billing, omit_zeros),
+        Ok(None) => {}
+        Err(err) => warn!(
+            "failed to poll Copilot billing for org `{}`, skipping: {}",
+            org, err
+        ),
+    }
 
     info!("polled org billing for `{}`", org);
 
-    Ok(())
+    Ok(actions_billing.total_minutes_used)
 }
 
-fn set_metrics_actions_billing(org: &str, actions_billing: &ActionsBilling) {
-    ORG_BILLING_ACTIONS_TOTAL_MINUTES_USED
-        .with_label_values(&[org])
-        .set(actions_billing.total_minutes_used);
-    ORG_BILLING_ACTIONS_TOTAL_PAID_MINUTES_USED
-        .with_label_values(&[org])
-        .set(actions_billing.total_paid_minutes_used);
-    ORG_BILLING_ACTIONS_INCLUDED_MINUTES
-        .with_label_values(&[org])
-        .set(actions_billing.included_minutes);
+/// Fetches Copilot billing, or `None` for organisations without Copilot.
+async fn get_copilot_billing(org: &str) -> anyhow::Result<Option<CopilotBilling>> {
+    github::get_optional(
+        "org_copilot_billing",
+        format!("orgs/{}/copilot/billing", org),
+        None::<&()>,
+        &[StatusCode::NOT_FOUND, StatusCode::UNPROCESSABLE_ENTITY],
+    )
+    .await
+}
 
-    if let Some(m) = actions_billing.minutes_used_breakdown.ubuntu {
-        ORG_BILLING_ACTIONS_MINUTES_USED_BREAKDOWN
-            .with_label_values(&[org, UBUNTU])
-            .set(m);
-    }
+fn set_metrics_actions_billing(org: &str, actions_billing: &ActionsBilling, omit_zeros: bool) {
+    set_or_omit_zero(
+        &ORG_BILLING_ACTIONS_TOTAL_MINUTES_USED,
+        &[org],
+        actions_billing.total_minutes_used,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &ORG_BILLING_ACTIONS_TOTAL_PAID_MINUTES_USED,
+        &[org],
+        actions_billing.total_paid_minutes_used,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &ORG_BILLING_ACTIONS_INCLUDED_MINUTES,
+        &[org],
+        actions_billing.included_minutes,
+        omit_zeros,
+    );
+
+    let breakdown = &actions_billing.minutes_used_breakdown;
+
+    for (os, minutes) in [
+        (UBUNTU, breakdown.ubuntu),
+        (MACOS, breakdown.macos),
+        (WINDOWS, breakdown.windows),
+    ] {
+        if let Some(m) = minutes {
+            set_or_omit_zero(
+                &ORG_BILLING_ACTIONS_MINUTES_USED_BREAKDOWN,
+                &[org, os],
+                m,
+                omit_zeros,
+            );
 
-    if let Some(m) = actions_billing.minutes_used_breakdown.macos {
-        ORG_BILLING_ACTIONS_MINUTES_USED_BREAKDOWN
-            .with_label_values(&[org, MACOS])
-            .set(m);
+            // Per-workflow timings don't cover e.g. deleted workflows or
+            // repositories that aren't polled, so the two rarely match exactly.
+            let workflow_minutes = billable_ms_for_owner(org, os) / 60_000.0;
+            ACTIONS_BILLING_RECONCILIATION_DIFF_MINUTES
+                .with_label_values(&[org, os])
+                .set(m - workflow_minutes);
+            expiry::touch(&*ACTIONS_BILLING_RECONCILIATION_DIFF_MINUTES, &[org, os]);
+        }
     }
 
-    if let Some(m) = actions_billing.minutes_used_breakdown.windows {
-        ORG_BILLING_ACTIONS_MINUTES_USED_BREAKDOWN
-            .with_label_values(&[org, WINDOWS])
-            .set(m);
+    ORG_BILLING_DISTINCT_RUNNER_TYPES
+        .with_label_values(&[org])
+        .set(breakdown.runner_types() as f64);
+    expiry::touch(&*ORG_BILLING_DISTINCT_RUNNER_TYPES, &[org]);
+}
+
+fn set_metrics_packages_billing(org: &str, packages_billing: &PackagesBilling, omit_zeros: bool) {
+    set_or_omit_zero(
+        &ORG_BILLING_PACKAGES_INCLUDED_GIGABYTES_BANDWIDTH,
+        &[org],
+        packages_billing.included_gigabytes_bandwidth,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &ORG_BILLING_PACKAGES_TOTAL_GIGABYTES_BANDWIDTH_USED,
+        &[org],
+        packages_billing.total_gigabytes_bandwidth_used,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &ORG_BILLING_PACKAGES_TOTAL_PAID_GIGABYTES_BANDWIDTH_USED,
+        &[org],
+        packages_billing.total_paid_gigabytes_bandwidth_used,
+        omit_zeros,
+    );
+}
+
+fn set_metrics_shared_storage_billing(
+    org: &str,
+    shared_storage_billing: &SharedStorageBilling,
+    omit_zeros: bool,
+) {
+    set_or_omit_zero(
+        &ORG_BILLING_SHARED_STORAGE_DAYS_LEFT_IN_BILLING_CYCLE,
+        &[org],
+        shared_storage_billing.days_left_in_billing_cycle,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &ORG_BILLING_SHARED_STORAGE_ESTIMATED_PAID_STORAGE_FOR_MONTH,
+        &[org],
+        shared_storage_billing.estimated_paid_storage_for_month,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &ORG_BILLING_SHARED_STORAGE_ESTIMATED_STORAGE_FOR_MONTH,
+        &[org],
+        shared_storage_billing.estimated_storage_for_month,
+        omit_zeros,
+    );
+
+    set_metrics_billing_cycle(org, shared_storage_billing.days_left_in_billing_cycle);
+}
+
+fn set_metrics_estimated_cost(
+    org: &str,
+    actions_billing: &ActionsBilling,
+    packages_billing: &PackagesBilling,
+    shared_storage_billing: &SharedStorageBilling,
+    rates: &CostRates,
+    omit_zeros: bool,
+) {
+    let breakdown = &actions_billing.minutes_used_breakdown;
+    let actions = [
+        (UBUNTU, breakdown.ubuntu),
+        (MACOS, breakdown.macos),
+        (WINDOWS, breakdown.windows),
+    ]
+    .iter()
+    .filter_map(|(os, minutes)| minutes.map(|m| m * rates.per_min(os)))
+    .sum::<f64>();
+    let packages = packages_billing.total_paid_gigabytes_bandwidth_used * rates.bandwidth_per_gb;
+    let storage = shared_storage_billing.estimated_paid_storage_for_month * rates.storage_per_gb;
+
+    for (category, cost) in [
+        ("actions", actions),
+        ("packages", packages),
+        ("storage", storage),
+    ] {
+        set_or_omit_zero(
+            &ORG_BILLING_ESTIMATED_COST_USD,
+            &[org, category],
+            cost,
+            omit_zeros,
+        );
     }
 }
 
-fn set_metrics_packages_billing(org: &str, packages_billing: &PackagesBilling) {
-    ORG_BILLING_PACKAGES_INCLUDED_GIGABYTES_BANDWIDTH
-        .with_label_values(&[org])
-        .set(packages_billing.included_gigabytes_bandwidth);
-    ORG_BILLING_PACKAGES_TOTAL_GIGABYTES_BANDWIDTH_USED
+/// Billing responses carry no cycle dates, only the days left in the current
+/// cycle: the end is derived from that, and the start assumes monthly cycles.
+fn set_metrics_billing_cycle(org: &str, days_left_in_billing_cycle: f64) {
+    let today = Utc::now().naive_utc().date();
+    let end = today + ChronoDuration::days(days_left_in_billing_cycle as i64);
+
+    let (year, month) = match end.month() {
+        1 => (end.year() - 1, 12),
+        m => (end.year(), m - 1),
+    };
+    let start = (1..=end.day())
+        .rev()
+        .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+        .expect("every month has a first day");
+
+    ORG_BILLING_CYCLE_START_TIMESTAMP
         .with_label_values(&[org])
-        .set(packages_billing.total_gigabytes_bandwidth_used);
-    ORG_BILLING_PACKAGES_TOTAL_PAID_GIGABYTES_BANDWIDTH_USED
+        .set(start_of_day_timestamp(start));
+    ORG_BILLING_CYCLE_END_TIMESTAMP
         .with_label_values(&[org])
-        .set(packages_billing.total_paid_gigabytes_bandwidth_used);
+        .set(start_of_day_timestamp(end));
+
+    expiry::touch(&*ORG_BILLING_CYCLE_START_TIMESTAMP, &[org]);
+    expiry::touch(&*ORG_BILLING_CYCLE_END_TIMESTAMP, &[org]);
 }
 
-fn set_metrics_shared_storage_billing(org: &str, shared_storage_billing: &SharedStorageBilling) {
-    ORG_BILLING_SHARED_STORAGE_DAYS_LEFT_IN_BILLING_CYCLE
-        .with_label_values(&[org])
-        .set(shared_storage_billing.days_left_in_billing_cycle);
-    ORG_BILLING_SHARED_STORAGE_ESTIMATED_PAID_STORAGE_FOR_MONTH
-        .with_label_values(&[org])
-        .set(shared_storage_billing.estimated_paid_storage_for_month);
-    ORG_BILLING_SHARED_STORAGE_ESTIMATED_STORAGE_FOR_MONTH
-        .with_label_values(&[org])
-        .set(shared_storage_billing.estimated_storage_for_month);
+fn start_of_day_timestamp(date: NaiveDate) -> f64 {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+
+    Utc.from_utc_datetime(&midnight).timestamp() as f64
+}
+
+fn set_metrics_copilot_billing(org: &str, copilot_billing: &CopilotBilling, omit_zeros: bool) {
+    set_or_omit_zero(
+        &ORG_COPILOT_SEATS_TOTAL,
+        &[org],
+        copilot_billing.seat_breakdown.total,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &ORG_COPILOT_SEATS_ACTIVE,
+        &[org],
+        copilot_billing.seat_breakdown.active_this_cycle,
+        omit_zeros,
+    );
 }
 
 #[serde_as]
@@ -130,6 +365,20 @@ pub struct MinutesUsedBreakdown {
     pub macos: Option<f64>,
     #[serde(rename = "WINDOWS")]
     pub windows: Option<f64>,
+    /// Other runner types, e.g. larger runners like `UBUNTU_16_CORE`.
+    #[serde(flatten)]
+    pub other: HashMap<String, f64>,
+}
+
+impl MinutesUsedBreakdown {
+    /// Number of runner types minutes were reported for.
+    pub fn runner_types(&self) -> usize {
+        [self.ubuntu, self.macos, self.windows]
+            .iter()
+            .filter(|m| m.is_some())
+            .count()
+            + self.other.len()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -146,7 +395,24 @@ pub struct SharedStorageBilling {
     pub estimated_storage_for_month: f64,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CopilotBilling {
+    pub seat_breakdown: CopilotSeatBreakdown,
+}
+
+#[derive(Debug, Deserialize)]
+#[non_exhaustive]
+pub struct CopilotSeatBreakdown {
+    pub total: f64,
+    pub active_this_cycle: f64,
+}
+
 lazy_static! {
+    pub static ref BILLING_TOTAL_MINUTES_USED_ALL_ORGS: Gauge = register_gauge!(
+        "github_billing_total_minutes_used_all_orgs",
+        "Github Actions total minutes used summed across all configured organisations"
+    )
+    .unwrap();
     pub static ref ORG_BILLING_ACTIONS_TOTAL_MINUTES_USED: GaugeVec = register_gauge_vec!(
         "github_org_billing_actions_total_minutes_used",
         "Github Actions organisation billing total minutes used",
@@ -213,4 +479,46 @@ lazy_static! {
             &["organisation"]
         )
         .unwrap();
+    pub static ref ORG_COPILOT_SEATS_TOTAL: GaugeVec = register_gauge_vec!(
+        "github_org_copilot_seats_total",
+        "Github Copilot organisation seats",
+        &["organisation"]
+    )
+    .unwrap();
+    pub static ref ORG_COPILOT_SEATS_ACTIVE: GaugeVec = register_gauge_vec!(
+        "github_org_copilot_seats_active",
+        "Github Copilot organisation seats active in the current billing cycle",
+        &["organisation"]
+    )
+    .unwrap();
+    pub static ref ORG_BILLING_CYCLE_START_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "github_org_billing_cycle_start_timestamp_seconds",
+        "Github organisation billing cycle start, assuming monthly cycles",
+        &["organisation"]
+    )
+    .unwrap();
+    pub static ref ORG_BILLING_CYCLE_END_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "github_org_billing_cycle_end_timestamp_seconds",
+        "Github organisation billing cycle end, derived from the days left in the cycle",
+        &["organisation"]
+    )
+    .unwrap();
+    pub static ref ORG_BILLING_ESTIMATED_COST_USD: GaugeVec = register_gauge_vec!(
+        "github_org_billing_estimated_cost_usd",
+        "Estimated Github organisation billing cost in USD, from usage and the configured rates",
+        &["organisation", "category"]
+    )
+    .unwrap();
+    pub static ref ORG_BILLING_DISTINCT_RUNNER_TYPES: GaugeVec = register_gauge_vec!(
+        "github_org_billing_distinct_runner_types",
+        "Number of distinct runner types in the Github Actions minutes breakdown of the organisation",
+        &["organisation"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_BILLING_RECONCILIATION_DIFF_MINUTES: GaugeVec = register_gauge_vec!(
+        "github_actions_billing_reconciliation_diff_minutes",
+        "Github Actions organisation billing minutes minus the sum of its polled workflows' billable time",
+        &["organisation", "os"]
+    )
+    .unwrap();
 }
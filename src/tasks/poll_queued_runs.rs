@@ -0,0 +1,141 @@
+use super::runs::get_runs;
+use crate::{
+    expiry, github, metrics, pause, repositories::Repositories, rotation::Rotation,
+    shutdown::Shutdown, types::Repository, watchdog,
+};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, register_int_gauge_vec, GaugeVec, IntGaugeVec};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// Statuses of runs that haven't started yet.
+static PENDING_STATUSES: &[&str] = &["queued", "waiting"];
+
+pub async fn poll_queued_runs(
+    github_workflows: Repositories,
+    pending_threshold: Duration,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        watchdog::beat("queued_runs", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                if let Err(err) = poll_queued_runs_for_repo(repo, pending_threshold).await {
+                    metrics::poll_failed("queued_runs", &err);
+                    error!("failed to poll queued runs for repo {}: {}", repo, err);
+                } else {
+                    metrics::poll_succeeded("queued_runs");
+                    info!("polled queued runs for {}", repo);
+                }
+            }
+
+            metrics::poll_cycle_completed("queued_runs", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+async fn poll_queued_runs_for_repo(
+    repo: &Repository,
+    pending_threshold: Duration,
+) -> anyhow::Result<()> {
+    let threshold = chrono::Duration::from_std(pending_threshold)?;
+    let now = Utc::now();
+    let labels = [repo.owner.as_str(), repo.name.as_str()];
+
+    // Only the count is needed, which comes with the first page.
+    let in_progress = match github::get_optional::<WorkflowRuns, _>(
+        "repo_runs",
+        format!(
+            "repos/{owner}/{repo}/actions/runs",
+            owner = repo.owner,
+            repo = repo.name,
+        ),
+        Some(&[("status", "in_progress"), ("per_page", "1")]),
+        &[StatusCode::NOT_FOUND],
+    )
+    .await?
+    {
+        Some(runs) => runs.total_count,
+        None => {
+            info!("Actions are disabled for repo {}, skipping", repo);
+            let _ = ACTIONS_RUNS_QUEUED.remove_label_values(&labels);
+            let _ = ACTIONS_RUNS_IN_PROGRESS.remove_label_values(&labels);
+            return Ok(());
+        }
+    };
+
+    let mut over_threshold = 0;
+    let mut queued = 0;
+
+    for status in PENDING_STATUSES {
+        for run in get_runs::<WorkflowRun>(repo, &[("status", *status)]).await? {
+            if now - run.created_at > threshold {
+                over_threshold += 1;
+            }
+
+            if *status == "queued" {
+                queued += 1;
+            }
+        }
+    }
+
+    ACTIONS_RUNS_QUEUED.with_label_values(&labels).set(queued);
+    expiry::touch(&*ACTIONS_RUNS_QUEUED, &labels);
+    ACTIONS_RUNS_IN_PROGRESS
+        .with_label_values(&labels)
+        .set(in_progress as i64);
+    expiry::touch(&*ACTIONS_RUNS_IN_PROGRESS, &labels);
+
+    ACTIONS_RUNS_PENDING_OVER_THRESHOLD
+        .with_label_values(&[&repo.owner, &repo.name])
+        .set(f64::from(over_threshold));
+    expiry::touch(
+        &*ACTIONS_RUNS_PENDING_OVER_THRESHOLD,
+        &[&repo.owner, &repo.name],
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRuns {
+    pub total_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRun {
+    pub created_at: DateTime<Utc>,
+}
+
+lazy_static! {
+    pub static ref ACTIONS_RUNS_QUEUED: IntGaugeVec = register_int_gauge_vec!(
+        "github_actions_runs_queued",
+        "Number of Github Actions runs currently queued",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_RUNS_IN_PROGRESS: IntGaugeVec = register_int_gauge_vec!(
+        "github_actions_runs_in_progress",
+        "Number of Github Actions runs currently in progress",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_RUNS_PENDING_OVER_THRESHOLD: GaugeVec = register_gauge_vec!(
+        "github_actions_runs_pending_over_threshold",
+        "Number of Github Actions runs queued or waiting for longer than the pending threshold",
+        &["owner", "repository"]
+    )
+    .unwrap();
+}
@@ -0,0 +1,104 @@
+use crate::{expiry, github, metrics, pause, shutdown::Shutdown, watchdog};
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, GaugeVec};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub async fn poll_rate_limit(sleep: Duration, mut shutdown: Shutdown) {
+    loop {
+        watchdog::beat("rate_limit", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            if let Err(err) = poll_rate_limit_once().await {
+                metrics::poll_failed("rate_limit", &err);
+                error!("failed to poll rate limit: {}", err);
+            } else {
+                metrics::poll_succeeded("rate_limit");
+                info!("polled rate limit");
+            }
+
+            metrics::poll_cycle_completed("rate_limit", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// Requests to `rate_limit` don't count against the quota themselves.
+async fn poll_rate_limit_once() -> anyhow::Result<()> {
+    let rate_limit = github::get::<RateLimit, _>("rate_limit", "rate_limit", None::<&()>).await?;
+
+    let resources = [
+        ("core", rate_limit.resources.core),
+        ("search", rate_limit.resources.search),
+        ("graphql", rate_limit.resources.graphql),
+    ];
+
+    for (resource, limit) in resources {
+        // GitHub Enterprise Server may not report every resource.
+        let limit = match limit {
+            Some(limit) => limit,
+            None => continue,
+        };
+
+        RATE_LIMIT_LIMIT
+            .with_label_values(&[resource])
+            .set(limit.limit);
+        expiry::touch(&*RATE_LIMIT_LIMIT, &[resource]);
+        RATE_LIMIT_REMAINING
+            .with_label_values(&[resource])
+            .set(limit.remaining);
+        expiry::touch(&*RATE_LIMIT_REMAINING, &[resource]);
+        RATE_LIMIT_RESET_TIMESTAMP
+            .with_label_values(&[resource])
+            .set(limit.reset);
+        expiry::touch(&*RATE_LIMIT_RESET_TIMESTAMP, &[resource]);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RateLimit {
+    pub resources: Resources,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resources {
+    pub core: Option<Limit>,
+    pub search: Option<Limit>,
+    pub graphql: Option<Limit>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Limit {
+    pub limit: f64,
+    pub remaining: f64,
+    pub reset: f64,
+}
+
+lazy_static! {
+    pub static ref RATE_LIMIT_LIMIT: GaugeVec = register_gauge_vec!(
+        "github_rate_limit_limit",
+        "Maximum number of Github API requests allowed per hour",
+        &["resource"]
+    )
+    .unwrap();
+    pub static ref RATE_LIMIT_REMAINING: GaugeVec = register_gauge_vec!(
+        "github_rate_limit_remaining",
+        "Number of Github API requests remaining in the current rate limit window",
+        &["resource"]
+    )
+    .unwrap();
+    pub static ref RATE_LIMIT_RESET_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "github_rate_limit_reset_timestamp",
+        "When the current Github API rate limit window resets, in seconds since the epoch",
+        &["resource"]
+    )
+    .unwrap();
+}
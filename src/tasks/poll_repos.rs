@@ -0,0 +1,402 @@
+use crate::{
+    expiry, github, metrics, pause, repositories::Repositories, rotation::Rotation,
+    shutdown::Shutdown, types::Repository, watchdog,
+};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, GaugeVec};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+pub async fn poll_repos(
+    github_workflows: Repositories,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    let mut check_suites = HashMap::new();
+
+    loop {
+        watchdog::beat("repos", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                let mut succeeded = false;
+
+                for (part, result) in poll_repo(repo, &mut check_suites).await {
+                    match result {
+                        Ok(()) => succeeded = true,
+                        Err(err) => {
+                            metrics::poll_failed("repos", &err);
+                            error!("failed to poll {} of repo {}: {}", part, repo, err);
+                        }
+                    }
+                }
+
+                if succeeded {
+                    metrics::poll_succeeded("repos");
+                    info!("polled repo {}", repo);
+                }
+            }
+
+            metrics::poll_cycle_completed("repos", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// Label pairs (`app`, `conclusion`) last exported per repository, so that
+/// those no longer present on the default branch HEAD can be removed.
+type CheckSuiteLabels = HashMap<Repository, HashSet<(String, String)>>;
+
+/// Polls each of the metrics of `repo` on its own, so that one failing, e.g.
+/// for lack of permissions, doesn't hold back the others. Answers how each
+/// part went.
+async fn poll_repo(
+    repo: &Repository,
+    check_suites: &mut CheckSuiteLabels,
+) -> Vec<(&'static str, anyhow::Result<()>)> {
+    let mut results = Vec::new();
+
+    match github::get::<RepositoryDetails, _>(
+        "repo",
+        format!("repos/{owner}/{repo}", owner = repo.owner, repo = repo.name),
+        None::<&()>,
+    )
+    .await
+    {
+        Ok(details) => {
+            REPO_OWNER_TYPE
+                .with_label_values(&[&repo.owner, &repo.name, &details.owner.kind])
+                .set(1.0);
+            expiry::touch(
+                &*REPO_OWNER_TYPE,
+                &[&repo.owner, &repo.name, &details.owner.kind],
+            );
+
+            results.push((
+                "default branch protection",
+                poll_branch_protection(repo, &details.default_branch).await,
+            ));
+            results.push((
+                "check suites",
+                poll_check_suites(repo, &details.default_branch, check_suites).await,
+            ));
+        }
+        Err(err) => results.push(("details", Err(err))),
+    }
+
+    results.push(("webhooks", poll_webhooks(repo).await));
+    results.push((
+        "oldest open pull request",
+        poll_oldest_pull_request(repo).await,
+    ));
+    results.push(("oldest open issue", poll_oldest_issue(repo).await));
+
+    results
+}
+
+async fn poll_branch_protection(repo: &Repository, branch: &str) -> anyhow::Result<()> {
+    let labels = [repo.owner.as_str(), repo.name.as_str()];
+
+    match is_branch_protected(repo, branch).await? {
+        Some(protected) => {
+            REPO_DEFAULT_BRANCH_PROTECTED
+                .with_label_values(&labels)
+                .set(if protected { 1.0 } else { 0.0 });
+            expiry::touch(&*REPO_DEFAULT_BRANCH_PROTECTED, &labels);
+        }
+        None => {
+            let _ = REPO_DEFAULT_BRANCH_PROTECTED.remove_label_values(&labels);
+        }
+    }
+
+    Ok(())
+}
+
+async fn poll_webhooks(repo: &Repository) -> anyhow::Result<()> {
+    if let Some(hooks) = get_webhooks(repo).await? {
+        let failing = hooks
+            .iter()
+            .filter(|h| h.last_response.is_failing())
+            .count();
+
+        REPO_WEBHOOKS_TOTAL
+            .with_label_values(&[&repo.owner, &repo.name])
+            .set(hooks.len() as f64);
+        expiry::touch(&*REPO_WEBHOOKS_TOTAL, &[&repo.owner, &repo.name]);
+        REPO_WEBHOOKS_FAILING
+            .with_label_values(&[&repo.owner, &repo.name])
+            .set(failing as f64);
+        expiry::touch(&*REPO_WEBHOOKS_FAILING, &[&repo.owner, &repo.name]);
+    }
+
+    Ok(())
+}
+
+async fn poll_oldest_pull_request(repo: &Repository) -> anyhow::Result<()> {
+    let oldest_pull_request = get_oldest_open(repo, "repo_pulls", "pulls", "1")
+        .await?
+        .into_iter()
+        .next();
+    set_age(
+        &REPO_OLDEST_OPEN_PULL_REQUEST_AGE,
+        repo,
+        oldest_pull_request,
+    );
+
+    Ok(())
+}
+
+async fn poll_oldest_issue(repo: &Repository) -> anyhow::Result<()> {
+    // The issues endpoint also lists pull requests, so a single item isn't
+    // enough to find the oldest actual issue.
+    let oldest_issue = get_oldest_open(repo, "repo_issues", "issues", "100")
+        .await?
+        .into_iter()
+        .find(|item| item.pull_request.is_none());
+    set_age(&REPO_OLDEST_OPEN_ISSUE_AGE, repo, oldest_issue);
+
+    Ok(())
+}
+
+async fn poll_check_suites(
+    repo: &Repository,
+    branch: &str,
+    check_suites: &mut CheckSuiteLabels,
+) -> anyhow::Result<()> {
+    let suites = github::get::<CheckSuites, _>(
+        "check_suites",
+        format!(
+            "repos/{owner}/{repo}/commits/{branch}/check-suites",
+            owner = repo.owner,
+            repo = repo.name,
+            branch = branch,
+        ),
+        Some(&[("per_page", "100")]),
+    )
+    .await?;
+
+    let mut counts = HashMap::<(String, String), u32>::new();
+
+    for suite in suites.check_suites {
+        // Suites still running have no conclusion yet, report their status.
+        let conclusion = suite.conclusion.unwrap_or(suite.status);
+
+        *counts.entry((suite.app.slug, conclusion)).or_default() += 1;
+    }
+
+    let previous = check_suites.entry(repo.clone()).or_default();
+
+    for (app, conclusion) in previous.iter() {
+        if !counts.contains_key(&(app.clone(), conclusion.clone())) {
+            let _ = CHECK_SUITES_STATUS.remove_label_values(&[
+                &repo.owner,
+                &repo.name,
+                app,
+                conclusion,
+            ]);
+        }
+    }
+
+    for ((app, conclusion), count) in &counts {
+        CHECK_SUITES_STATUS
+            .with_label_values(&[&repo.owner, &repo.name, app, conclusion])
+            .set(f64::from(*count));
+        expiry::touch(
+            &*CHECK_SUITES_STATUS,
+            &[&repo.owner, &repo.name, app, conclusion],
+        );
+    }
+
+    *previous = counts.into_iter().map(|(labels, _)| labels).collect();
+
+    Ok(())
+}
+
+/// Lists the repository's webhooks, or `None` without admin access to it.
+async fn get_webhooks(repo: &Repository) -> anyhow::Result<Option<Vec<Hook>>> {
+    github::get_optional(
+        "repo_hooks",
+        format!(
+            "repos/{owner}/{repo}/hooks",
+            owner = repo.owner,
+            repo = repo.name,
+        ),
+        Some(&[("per_page", "100")]),
+        &[StatusCode::FORBIDDEN, StatusCode::NOT_FOUND],
+    )
+    .await
+}
+
+async fn get_oldest_open(
+    repo: &Repository,
+    endpoint: &'static str,
+    kind: &str,
+    per_page: &str,
+) -> anyhow::Result<Vec<Item>> {
+    github::get(
+        endpoint,
+        format!(
+            "repos/{owner}/{repo}/{kind}",
+            owner = repo.owner,
+            repo = repo.name,
+            kind = kind,
+        ),
+        Some(&[
+            ("state", "open"),
+            ("sort", "created"),
+            ("direction", "asc"),
+            ("per_page", per_page),
+        ]),
+    )
+    .await
+}
+
+fn set_age(gauge: &'static GaugeVec, repo: &Repository, oldest: Option<Item>) {
+    let labels = [repo.owner.as_str(), repo.name.as_str()];
+
+    match oldest {
+        Some(item) => {
+            gauge
+                .with_label_values(&labels)
+                .set((Utc::now() - item.created_at).num_seconds() as f64);
+            expiry::touch(gauge, &labels);
+        }
+        None => {
+            let _ = gauge.remove_label_values(&labels);
+        }
+    }
+}
+
+/// GitHub answers 404 when the branch has no protection rules, and 403 to
+/// tokens without admin rights on the repository, for which whether it is
+/// protected is unknown.
+async fn is_branch_protected(repo: &Repository, branch: &str) -> anyhow::Result<Option<bool>> {
+    let protection = github::get_or_status::<serde_json::Value, _>(
+        "branch_protection",
+        format!(
+            "repos/{owner}/{repo}/branches/{branch}/protection",
+            owner = repo.owner,
+            repo = repo.name,
+            branch = branch,
+        ),
+        None::<&()>,
+        &[StatusCode::NOT_FOUND, StatusCode::FORBIDDEN],
+    )
+    .await?;
+
+    Ok(match protection {
+        Ok(_) => Some(true),
+        Err(StatusCode::NOT_FOUND) => Some(false),
+        Err(_) => None,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepositoryDetails {
+    pub default_branch: String,
+    pub owner: Owner,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Owner {
+    /// Either `User` or `Organization`.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckSuites {
+    pub check_suites: Vec<CheckSuite>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckSuite {
+    pub status: String,
+    pub conclusion: Option<String>,
+    pub app: App,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct App {
+    pub slug: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Hook {
+    pub last_response: HookResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HookResponse {
+    /// Unset until the hook has been delivered at least once.
+    pub code: Option<u16>,
+}
+
+impl HookResponse {
+    fn is_failing(&self) -> bool {
+        matches!(self.code, Some(code) if !(200..300).contains(&code))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Item {
+    pub created_at: DateTime<Utc>,
+    pub pull_request: Option<serde_json::Value>,
+}
+
+lazy_static! {
+    pub static ref REPO_OWNER_TYPE: GaugeVec = register_gauge_vec!(
+        "github_repo_owner_type",
+        "Type of the Github repository owner, either User or Organization",
+        &["owner", "repository", "type"]
+    )
+    .unwrap();
+    pub static ref REPO_DEFAULT_BRANCH_PROTECTED: GaugeVec = register_gauge_vec!(
+        "github_repo_default_branch_protected",
+        "Whether the Github repository default branch is protected (1) or not (0)",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref CHECK_SUITES_STATUS: GaugeVec = register_gauge_vec!(
+        "github_check_suites_status",
+        "Github check suites on the default branch HEAD by app and conclusion",
+        &["owner", "repository", "app", "conclusion"]
+    )
+    .unwrap();
+    pub static ref REPO_WEBHOOKS_TOTAL: GaugeVec = register_gauge_vec!(
+        "github_repo_webhooks_total",
+        "Number of webhooks configured on the Github repository",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref REPO_WEBHOOKS_FAILING: GaugeVec = register_gauge_vec!(
+        "github_repo_webhooks_failing",
+        "Number of Github repository webhooks whose last delivery failed",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref REPO_OLDEST_OPEN_PULL_REQUEST_AGE: GaugeVec = register_gauge_vec!(
+        "github_oldest_open_pull_request_age_seconds",
+        "Age of the oldest open pull request in the Github repository",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref REPO_OLDEST_OPEN_ISSUE_AGE: GaugeVec = register_gauge_vec!(
+        "github_oldest_open_issue_age_seconds",
+        "Age of the oldest open issue in the Github repository",
+        &["owner", "repository"]
+    )
+    .unwrap();
+}
@@ -0,0 +1,172 @@
+use crate::{
+    expiry, github, metrics, pause, repositories::Repositories, shutdown::Shutdown,
+    types::Organisations, watchdog,
+};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+const PER_PAGE: usize = 100;
+
+/// Labels of one exported runner series.
+type RunnerLabels = [String; 5];
+
+/// Polls the self-hosted runners of every repository, when `github_workflows`
+/// is given, and of every organisation, when `orgs` is. Organisation runners
+/// have an empty `repository` label.
+pub async fn poll_runners(
+    github_workflows: Option<Repositories>,
+    orgs: Option<Organisations>,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    let mut exported = HashMap::new();
+
+    loop {
+        watchdog::beat("runners", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            let mut scopes = Vec::new();
+
+            if let Some(github_workflows) = &github_workflows {
+                scopes.extend(
+                    github_workflows
+                        .list()
+                        .await
+                        .into_iter()
+                        .map(|repo| (repo.owner.clone(), repo.name)),
+                );
+            }
+
+            if let Some(orgs) = &orgs {
+                scopes.extend(orgs.load().iter().map(|org| (org.clone(), String::new())));
+            }
+
+            for (owner, repository) in scopes {
+                let path = if repository.is_empty() {
+                    format!("orgs/{}/actions/runners", owner)
+                } else {
+                    format!("repos/{}/{}/actions/runners", owner, repository)
+                };
+
+                match list_runners(&path).await {
+                    Ok(Some(runners)) => {
+                        metrics::poll_succeeded("runners");
+
+                        let series = runners
+                            .iter()
+                            .map(|runner| {
+                                [
+                                    owner.clone(),
+                                    repository.clone(),
+                                    runner.name.clone(),
+                                    runner.status.clone(),
+                                    runner.busy.to_string(),
+                                ]
+                            })
+                            .collect::<HashSet<RunnerLabels>>();
+
+                        let previous = exported
+                            .insert((owner.clone(), repository.clone()), series.clone())
+                            .unwrap_or_default();
+                        set_metrics(&series, &previous);
+
+                        info!("polled self-hosted runners for {}", path);
+                    }
+                    Ok(None) => {
+                        metrics::poll_succeeded("runners");
+                        info!(
+                            "self-hosted runners of {} are not accessible, skipping",
+                            path
+                        );
+                    }
+                    Err(err) => {
+                        metrics::poll_failed("runners", &err);
+                        error!("failed to poll self-hosted runners for {}: {}", path, err);
+                    }
+                }
+            }
+
+            metrics::poll_cycle_completed("runners", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// Sets the series of the current runners, removing those of runners that
+/// went away or changed status.
+fn set_metrics(series: &HashSet<RunnerLabels>, previous: &HashSet<RunnerLabels>) {
+    for labels in previous.difference(series) {
+        let labels = labels.iter().map(String::as_str).collect::<Vec<_>>();
+        let _ = ACTIONS_RUNNER_STATUS.remove_label_values(&labels);
+    }
+
+    for labels in series {
+        let labels = labels.iter().map(String::as_str).collect::<Vec<_>>();
+        ACTIONS_RUNNER_STATUS.with_label_values(&labels).set(1);
+        expiry::touch(&*ACTIONS_RUNNER_STATUS, &labels);
+    }
+}
+
+/// All runners at `path`, or `None` when the token may not list them.
+async fn list_runners(path: &str) -> anyhow::Result<Option<Vec<Runner>>> {
+    let mut runners = Vec::new();
+
+    for page in 1.. {
+        let page = page.to_string();
+        let per_page = PER_PAGE.to_string();
+
+        let response = match github::get_optional::<Runners, _>(
+            "runners",
+            path,
+            Some(&[("per_page", per_page.as_str()), ("page", page.as_str())]),
+            &[StatusCode::FORBIDDEN, StatusCode::NOT_FOUND],
+        )
+        .await?
+        {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+
+        let last_page = response.runners.len() < PER_PAGE;
+        runners.extend(response.runners);
+
+        if last_page {
+            break;
+        }
+    }
+
+    Ok(Some(runners))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Runners {
+    pub runners: Vec<Runner>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Runner {
+    pub name: String,
+    pub status: String,
+    pub busy: bool,
+}
+
+lazy_static! {
+    pub static ref ACTIONS_RUNNER_STATUS: IntGaugeVec = register_int_gauge_vec!(
+        "github_actions_runner_status",
+        "Self-hosted Github Actions runners, always 1, labelled with their status and whether they're busy",
+        &["owner", "repository", "runner_name", "status", "busy"]
+    )
+    .unwrap();
+}
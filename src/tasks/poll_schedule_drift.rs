@@ -0,0 +1,283 @@
+use crate::{
+    expiry, github, metrics, pause,
+    repositories::Repositories,
+    rotation::Rotation,
+    shutdown::Shutdown,
+    types::{Repository, Workflow},
+    watchdog,
+};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, GaugeVec};
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+pub async fn poll_schedule_drift(
+    github_workflows: Repositories,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    let mut polled = HashMap::new();
+
+    loop {
+        watchdog::beat("schedule_drift", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            let repos = github_workflows.list().await;
+
+            for repo in &rotation.select(repos.clone()) {
+                for workflow in &github_workflows.workflows(repo).await {
+                    let polled = polled.entry((repo.clone(), workflow.id)).or_default();
+
+                    match poll_schedule_drift_for_workflow(repo, workflow, polled).await {
+                        Ok(()) => metrics::poll_succeeded("schedule_drift"),
+                        Err(err) => {
+                            metrics::poll_failed("schedule_drift", &err);
+                            error!(
+                                "failed to poll schedule drift for workflow {:?} in repo {}: {}",
+                                workflow, repo, err
+                            );
+                        }
+                    }
+                }
+            }
+
+            polled.retain(|(repo, _), _| repos.contains(repo));
+
+            metrics::poll_cycle_completed("schedule_drift", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// What was last seen of a workflow, so that neither its file nor its latest
+/// scheduled run are processed again while they haven't changed.
+#[derive(Default)]
+struct Polled {
+    run_id: Option<u64>,
+    drift: Option<i64>,
+    /// Blob sha of the workflow file along with the schedules parsed from it.
+    file: Option<(String, Vec<Schedule>)>,
+}
+
+async fn poll_schedule_drift_for_workflow(
+    repo: &Repository,
+    workflow: &Workflow,
+    polled: &mut Polled,
+) -> anyhow::Result<()> {
+    let runs = github::get::<WorkflowRuns, _>(
+        "workflow_runs",
+        format!(
+            "repos/{owner}/{repo}/actions/workflows/{workflow_id}/runs",
+            owner = repo.owner,
+            repo = repo.name,
+            workflow_id = workflow.id,
+        ),
+        Some(&[("event", "schedule"), ("per_page", "1")]),
+    )
+    .await?;
+
+    // Workflows that never ran on a schedule don't need their file fetched.
+    let run = match runs.workflow_runs.first() {
+        Some(run) => run,
+        None => return Ok(()),
+    };
+    let labels = [
+        repo.owner.as_str(),
+        repo.name.as_str(),
+        workflow.name.as_str(),
+    ];
+
+    // The drift of a run doesn't change, only a newer one needs the file.
+    if polled.run_id == Some(run.id) {
+        if polled.drift.is_some() {
+            expiry::touch(&*ACTIONS_SCHEDULE_DRIFT_SECONDS, &labels);
+        }
+
+        metrics::api_call_saved("schedule_drift_same_run");
+        return Ok(());
+    }
+
+    let file = github::get::<Content, _>(
+        "repo_contents",
+        format!(
+            "repos/{owner}/{repo}/contents/{path}",
+            owner = repo.owner,
+            repo = repo.name,
+            path = workflow.path,
+        ),
+        None::<&()>,
+    )
+    .await?;
+
+    let schedules = match &polled.file {
+        Some((sha, schedules)) if *sha == file.sha => schedules,
+        _ => {
+            let schedules = parse_schedules(&file.decode()?)?;
+
+            &polled.file.insert((file.sha, schedules)).1
+        }
+    };
+
+    let drift = schedule_drift(schedules, run.created_at);
+
+    if let Some(drift) = drift {
+        ACTIONS_SCHEDULE_DRIFT_SECONDS
+            .with_label_values(&labels)
+            .set(drift as f64);
+        expiry::touch(&*ACTIONS_SCHEDULE_DRIFT_SECONDS, &labels);
+
+        info!(
+            "polled schedule drift for {}:{}: {}s",
+            repo, workflow.name, drift
+        );
+    }
+
+    polled.run_id = Some(run.id);
+    polled.drift = drift;
+
+    Ok(())
+}
+
+/// Extracts the `on.schedule[].cron` expressions from a workflow file.
+fn parse_schedules(workflow_file: &str) -> anyhow::Result<Vec<Schedule>> {
+    let file = serde_yaml::from_str::<WorkflowFile>(workflow_file)?;
+
+    let crons = file
+        .on
+        .as_ref()
+        .and_then(|on| on.get("schedule"))
+        .and_then(Value::as_sequence)
+        .map(|schedule| {
+            schedule
+                .iter()
+                .filter_map(|entry| entry.get("cron").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    crons
+        .into_iter()
+        .map(|cron| {
+            let expression = to_cron_expression(cron)
+                .ok_or_else(|| anyhow::anyhow!("invalid cron expression `{}`", cron))?;
+
+            Ok(Schedule::from_str(&expression)?)
+        })
+        .collect()
+}
+
+/// Converts a five-field POSIX cron expression, as used by GitHub, into the
+/// seconds-first format expected by the `cron` crate, which also numbers
+/// days of the week from 1 (Sunday) rather than 0.
+fn to_cron_expression(github_cron: &str) -> Option<String> {
+    let fields = github_cron.split_whitespace().collect::<Vec<_>>();
+
+    if let [minute, hour, day_of_month, month, day_of_week] = fields[..] {
+        let day_of_week = day_of_week
+            .split(',')
+            .map(|part| {
+                let (values, step) = match part.split_once('/') {
+                    Some((values, step)) => (values, Some(step)),
+                    None => (part, None),
+                };
+
+                let values = values
+                    .split('-')
+                    .map(|v| match v.parse::<u8>() {
+                        Ok(day) => (day % 7 + 1).to_string(),
+                        Err(_) => v.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("-");
+
+                match step {
+                    Some(step) => format!("{}/{}", values, step),
+                    None => values,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Some(format!(
+            "0 {} {} {} {} {}",
+            minute, hour, day_of_month, month, day_of_week
+        ))
+    } else {
+        None
+    }
+}
+
+/// Seconds between the latest scheduled time preceding `created_at` and
+/// `created_at` itself, across all of the workflow's schedules.
+fn schedule_drift(schedules: &[Schedule], created_at: DateTime<Utc>) -> Option<i64> {
+    let window_start = created_at - chrono::Duration::days(1);
+
+    schedules
+        .iter()
+        .filter_map(|schedule| {
+            schedule
+                .after(&window_start)
+                .take_while(|expected| *expected <= created_at)
+                .last()
+        })
+        .map(|expected| (created_at - expected).num_seconds())
+        .min()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRuns {
+    pub workflow_runs: Vec<WorkflowRun>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRun {
+    pub id: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Content {
+    pub sha: String,
+    pub content: String,
+}
+
+impl Content {
+    fn decode(&self) -> anyhow::Result<String> {
+        let content = self
+            .content
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>();
+
+        Ok(String::from_utf8(base64::decode(content)?)?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkflowFile {
+    #[serde(default)]
+    on: Option<Value>,
+}
+
+lazy_static! {
+    pub static ref ACTIONS_SCHEDULE_DRIFT_SECONDS: GaugeVec = register_gauge_vec!(
+        "github_actions_schedule_drift_seconds",
+        "Github Actions delay between a scheduled run's expected and actual start",
+        &["owner", "repository", "workflow"]
+    )
+    .unwrap();
+}
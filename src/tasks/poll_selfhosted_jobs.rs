@@ -0,0 +1,222 @@
+use super::runs::get_completed_runs;
+use crate::{
+    github, metrics, pause, repositories::Repositories, rotation::Rotation, shutdown::Shutdown,
+    types::Repository, watchdog,
+};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+static SELF_HOSTED_LABEL: &str = "self-hosted";
+const PER_PAGE: usize = 100;
+
+pub async fn poll_selfhosted_jobs(
+    github_workflows: Repositories,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    let mut counted = HashMap::new();
+
+    loop {
+        watchdog::beat("selfhosted_jobs", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                match poll_selfhosted_jobs_for_repo(repo, &mut counted).await {
+                    Ok(()) => metrics::poll_succeeded("selfhosted_jobs"),
+                    Err(err) => {
+                        metrics::poll_failed("selfhosted_jobs", &err);
+                        error!("failed to poll self-hosted jobs for repo {}: {}", repo, err);
+                    }
+                }
+            }
+
+            metrics::poll_cycle_completed("selfhosted_jobs", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// Latest attempt counted of each recent run, by run id, along with when the
+/// run was created so that it is forgotten once out of the window.
+type CountedAttempts = HashMap<u64, (u32, DateTime<Utc>)>;
+
+/// Counts the self-hosted jobs of every run attempt completed since the
+/// previous poll, re-runs included. The first poll of a repository only
+/// records the attempts completed so far, so that historical ones aren't
+/// counted.
+///
+/// Only runs created within the last day are looked at, attempts completing
+/// later than that are missed.
+async fn poll_selfhosted_jobs_for_repo(
+    repo: &Repository,
+    counted: &mut HashMap<Repository, CountedAttempts>,
+) -> anyhow::Result<()> {
+    let window_start = Utc::now() - ChronoDuration::days(1);
+    let runs = get_completed_runs::<WorkflowRun>(repo, window_start).await?;
+
+    let counted = match counted.get_mut(repo) {
+        Some(counted) => counted,
+        None => {
+            let attempts = runs
+                .iter()
+                .map(|run| (run.id, (run.run_attempt, run.created_at)))
+                .collect();
+            counted.insert(repo.clone(), attempts);
+            return Ok(());
+        }
+    };
+
+    let mut selfhosted_jobs = 0;
+
+    for run in &runs {
+        let counted_attempt = counted.get(&run.id).map_or(0, |(attempt, _)| *attempt);
+
+        for attempt in counted_attempt + 1..=run.run_attempt {
+            let jobs = get_attempt_jobs(repo, run.id, attempt).await?;
+
+            let count = jobs
+                .iter()
+                .filter(|job| {
+                    job.labels
+                        .iter()
+                        .any(|l| l.eq_ignore_ascii_case(SELF_HOSTED_LABEL))
+                })
+                .count() as u64;
+
+            ACTIONS_SELFHOSTED_JOBS
+                .with_label_values(&[&repo.owner, &repo.name])
+                .inc_by(count);
+            selfhosted_jobs += count;
+
+            counted.insert(run.id, (attempt, run.created_at));
+        }
+    }
+
+    counted.retain(|_, (_, created_at)| *created_at >= window_start);
+
+    info!(
+        "polled {} new self-hosted jobs for {}",
+        selfhosted_jobs, repo
+    );
+
+    Ok(())
+}
+
+/// Jobs of `attempt` of the run, on every page: a matrix can fan out to more
+/// jobs than fit on one.
+async fn get_attempt_jobs(
+    repo: &Repository,
+    run_id: u64,
+    attempt: u32,
+) -> anyhow::Result<Vec<Job>> {
+    let per_page = PER_PAGE.to_string();
+    let mut jobs = Vec::new();
+
+    for page in 1.. {
+        let page = page.to_string();
+
+        let response = github::get::<Jobs, _>(
+            "run_attempt_jobs",
+            format!(
+                "repos/{owner}/{repo}/actions/runs/{run_id}/attempts/{attempt}/jobs",
+                owner = repo.owner,
+                repo = repo.name,
+                run_id = run_id,
+                attempt = attempt,
+            ),
+            Some(&[("per_page", per_page.as_str()), ("page", page.as_str())]),
+        )
+        .await?;
+
+        let last_page = response.jobs.len() < PER_PAGE;
+        jobs.extend(response.jobs);
+
+        if last_page {
+            break;
+        }
+    }
+
+    Ok(jobs)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRun {
+    pub id: u64,
+    /// Goes up with every re-run, which keeps the run id.
+    #[serde(default = "first_attempt")]
+    pub run_attempt: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+fn first_attempt() -> u32 {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Jobs {
+    pub jobs: Vec<Job>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Job {
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+lazy_static! {
+    pub static ref ACTIONS_SELFHOSTED_JOBS: IntCounterVec = register_int_counter_vec!(
+        "github_actions_selfhosted_jobs_total",
+        "Github Actions jobs completed on self-hosted runners",
+        &["organisation", "repository"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::testing::mock_github;
+    use hyper::{Body, Response};
+
+    #[tokio::test]
+    async fn follows_every_page_of_attempt_jobs() {
+        mock_github("matrix", |req| async move {
+            let labels = r#"{"labels": ["self-hosted", "linux"]}"#;
+            let page = req
+                .uri()
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .find_map(|param| param.strip_prefix("page="));
+            let jobs = match page {
+                Some("1") => vec![labels; PER_PAGE],
+                Some("2") => vec![labels],
+                _ => Vec::new(),
+            };
+
+            Response::new(Body::from(format!(
+                r#"{{"total_count": {}, "jobs": [{}]}}"#,
+                PER_PAGE + 1,
+                jobs.join(",")
+            )))
+        });
+
+        let repo = "matrix/app".parse::<Repository>().unwrap();
+        let jobs = get_attempt_jobs(&repo, 1, 1).await.unwrap();
+
+        assert_eq!(jobs.len(), PER_PAGE + 1);
+    }
+}
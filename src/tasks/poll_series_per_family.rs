@@ -0,0 +1,33 @@
+use crate::{metrics, shutdown::Shutdown, watchdog};
+use lazy_static::lazy_static;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use std::time::{Duration, Instant};
+
+pub async fn poll_series_per_family(sleep: Duration, mut shutdown: Shutdown) {
+    loop {
+        watchdog::beat("series_per_family", sleep);
+
+        let started = Instant::now();
+
+        for mf in prometheus::gather() {
+            SERIES_PER_FAMILY
+                .with_label_values(&[mf.get_name()])
+                .set(mf.get_metric().len() as i64);
+        }
+
+        metrics::poll_cycle_completed("series_per_family", started);
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref SERIES_PER_FAMILY: IntGaugeVec = register_int_gauge_vec!(
+        "github_exporter_series_per_family",
+        "Number of distinct label combinations exposed by each metric family",
+        &["metric"]
+    )
+    .unwrap();
+}
@@ -0,0 +1,233 @@
+use super::poll_orgs_billing::{ActionsBilling, PackagesBilling, SharedStorageBilling};
+use crate::{
+    github,
+    metrics::{self, set_or_omit_zero},
+    pause,
+    shutdown::Shutdown,
+    types::{MACOS, UBUNTU, WINDOWS},
+    watchdog,
+};
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, GaugeVec};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// Polls the billing of personal accounts, the counterpart of
+/// `poll_orgs_billing` for repositories owned by users.
+pub async fn poll_users_billing(
+    users: Vec<String>,
+    omit_zeros: bool,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        watchdog::beat("users_billing", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for user in &users {
+                match poll_user_billing(user, omit_zeros).await {
+                    Ok(()) => metrics::poll_succeeded("users_billing"),
+                    Err(err) => {
+                        metrics::poll_failed("users_billing", &err);
+                        error!("failed to poll user billing for user `{}`: {}", user, err);
+                    }
+                }
+            }
+
+            metrics::poll_cycle_completed("users_billing", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+async fn poll_user_billing(user: &str, omit_zeros: bool) -> anyhow::Result<()> {
+    let actions_billing_fut = github::get::<ActionsBilling, _>(
+        "user_billing_actions",
+        format!("users/{}/settings/billing/actions", user),
+        None::<&()>,
+    );
+
+    let packages_billing_fut = github::get::<PackagesBilling, _>(
+        "user_billing_packages",
+        format!("users/{}/settings/billing/packages", user),
+        None::<&()>,
+    );
+
+    let shared_storage_billing_fut = github::get::<SharedStorageBilling, _>(
+        "user_billing_shared_storage",
+        format!("users/{}/settings/billing/shared-storage", user),
+        None::<&()>,
+    );
+
+    let (actions_billing_res, packages_billing_res, shared_storage_billing_res) = tokio::join!(
+        actions_billing_fut,
+        packages_billing_fut,
+        shared_storage_billing_fut
+    );
+
+    set_metrics_actions_billing(user, &actions_billing_res?, omit_zeros);
+    set_metrics_packages_billing(user, &packages_billing_res?, omit_zeros);
+    set_metrics_shared_storage_billing(user, &shared_storage_billing_res?, omit_zeros);
+
+    info!("polled user billing for `{}`", user);
+
+    Ok(())
+}
+
+fn set_metrics_actions_billing(user: &str, actions_billing: &ActionsBilling, omit_zeros: bool) {
+    set_or_omit_zero(
+        &USER_BILLING_ACTIONS_TOTAL_MINUTES_USED,
+        &[user],
+        actions_billing.total_minutes_used,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &USER_BILLING_ACTIONS_TOTAL_PAID_MINUTES_USED,
+        &[user],
+        actions_billing.total_paid_minutes_used,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &USER_BILLING_ACTIONS_INCLUDED_MINUTES,
+        &[user],
+        actions_billing.included_minutes,
+        omit_zeros,
+    );
+
+    let breakdown = &actions_billing.minutes_used_breakdown;
+
+    for (os, minutes) in [
+        (UBUNTU, breakdown.ubuntu),
+        (MACOS, breakdown.macos),
+        (WINDOWS, breakdown.windows),
+    ] {
+        if let Some(m) = minutes {
+            set_or_omit_zero(
+                &USER_BILLING_ACTIONS_MINUTES_USED_BREAKDOWN,
+                &[user, os],
+                m,
+                omit_zeros,
+            );
+        }
+    }
+}
+
+fn set_metrics_packages_billing(user: &str, packages_billing: &PackagesBilling, omit_zeros: bool) {
+    set_or_omit_zero(
+        &USER_BILLING_PACKAGES_INCLUDED_GIGABYTES_BANDWIDTH,
+        &[user],
+        packages_billing.included_gigabytes_bandwidth,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &USER_BILLING_PACKAGES_TOTAL_GIGABYTES_BANDWIDTH_USED,
+        &[user],
+        packages_billing.total_gigabytes_bandwidth_used,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &USER_BILLING_PACKAGES_TOTAL_PAID_GIGABYTES_BANDWIDTH_USED,
+        &[user],
+        packages_billing.total_paid_gigabytes_bandwidth_used,
+        omit_zeros,
+    );
+}
+
+fn set_metrics_shared_storage_billing(
+    user: &str,
+    shared_storage_billing: &SharedStorageBilling,
+    omit_zeros: bool,
+) {
+    set_or_omit_zero(
+        &USER_BILLING_SHARED_STORAGE_DAYS_LEFT_IN_BILLING_CYCLE,
+        &[user],
+        shared_storage_billing.days_left_in_billing_cycle,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &USER_BILLING_SHARED_STORAGE_ESTIMATED_PAID_STORAGE_FOR_MONTH,
+        &[user],
+        shared_storage_billing.estimated_paid_storage_for_month,
+        omit_zeros,
+    );
+    set_or_omit_zero(
+        &USER_BILLING_SHARED_STORAGE_ESTIMATED_STORAGE_FOR_MONTH,
+        &[user],
+        shared_storage_billing.estimated_storage_for_month,
+        omit_zeros,
+    );
+}
+
+lazy_static! {
+    pub static ref USER_BILLING_ACTIONS_TOTAL_MINUTES_USED: GaugeVec = register_gauge_vec!(
+        "github_user_billing_actions_total_minutes_used",
+        "Github Actions user billing total minutes used",
+        &["user"]
+    )
+    .unwrap();
+    pub static ref USER_BILLING_ACTIONS_TOTAL_PAID_MINUTES_USED: GaugeVec = register_gauge_vec!(
+        "github_user_billing_actions_total_paid_minutes_used",
+        "Github Actions user billing total paid minutes used",
+        &["user"]
+    )
+    .unwrap();
+    pub static ref USER_BILLING_ACTIONS_INCLUDED_MINUTES: GaugeVec = register_gauge_vec!(
+        "github_user_billing_actions_included_minutes",
+        "Github Actions user billing included minutes",
+        &["user"]
+    )
+    .unwrap();
+    pub static ref USER_BILLING_ACTIONS_MINUTES_USED_BREAKDOWN: GaugeVec = register_gauge_vec!(
+        "github_user_billing_actions_minutes_used_breakdown",
+        "Github Actions user billing minutes breakdown",
+        &["user", "os"]
+    )
+    .unwrap();
+    pub static ref USER_BILLING_PACKAGES_TOTAL_GIGABYTES_BANDWIDTH_USED: GaugeVec =
+        register_gauge_vec!(
+            "github_user_billing_packages_total_gigabytes_bandwidth_used",
+            "Github Packages user billing total gigabytes bandwidth used",
+            &["user"]
+        )
+        .unwrap();
+    pub static ref USER_BILLING_PACKAGES_TOTAL_PAID_GIGABYTES_BANDWIDTH_USED: GaugeVec =
+        register_gauge_vec!(
+            "github_user_billing_packages_total_paid_gigabytes_bandwidth_used",
+            "Github Packages user billing total paid gigabytes bandwidth used",
+            &["user"]
+        )
+        .unwrap();
+    pub static ref USER_BILLING_PACKAGES_INCLUDED_GIGABYTES_BANDWIDTH: GaugeVec =
+        register_gauge_vec!(
+            "github_user_billing_packages_included_gigabytes_bandwidth",
+            "Github Packages user billing included gigabytes bandwidth",
+            &["user"]
+        )
+        .unwrap();
+    pub static ref USER_BILLING_SHARED_STORAGE_DAYS_LEFT_IN_BILLING_CYCLE: GaugeVec =
+        register_gauge_vec!(
+            "github_user_billing_shared_storage_days_left_in_billing_cycle",
+            "Github Shared Storage user billing days left in billing cycle",
+            &["user"]
+        )
+        .unwrap();
+    pub static ref USER_BILLING_SHARED_STORAGE_ESTIMATED_PAID_STORAGE_FOR_MONTH: GaugeVec =
+        register_gauge_vec!(
+            "github_user_billing_shared_storage_estimated_paid_storage_for_month",
+            "Github Shared Storage user billing estimated paid storage for month",
+            &["user"]
+        )
+        .unwrap();
+    pub static ref USER_BILLING_SHARED_STORAGE_ESTIMATED_STORAGE_FOR_MONTH: GaugeVec =
+        register_gauge_vec!(
+            "github_user_billing_shared_storage_estimated_storage_for_month",
+            "Github Shared Storage user billing estimated storage for month",
+            &["user"]
+        )
+        .unwrap();
+}
@@ -0,0 +1,117 @@
+use crate::{
+    expiry, github, metrics, pause,
+    repositories::Repositories,
+    rotation::Rotation,
+    shutdown::Shutdown,
+    types::{Repository, Workflow},
+    watchdog,
+};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, register_int_gauge_vec, GaugeVec, IntGaugeVec};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub async fn poll_workflow_last_run(
+    github_workflows: Repositories,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        watchdog::beat("workflow_last_run", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                for workflow in &github_workflows.workflows(repo).await {
+                    match poll_last_run_for_workflow(repo, workflow).await {
+                        Ok(()) => metrics::poll_succeeded("workflow_last_run"),
+                        Err(err) => {
+                            metrics::poll_failed("workflow_last_run", &err);
+                            error!(
+                                "failed to poll last run for workflow {:?} in repo {}: {}",
+                                workflow, repo, err
+                            );
+                        }
+                    }
+                }
+            }
+
+            metrics::poll_cycle_completed("workflow_last_run", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+async fn poll_last_run_for_workflow(repo: &Repository, workflow: &Workflow) -> anyhow::Result<()> {
+    let runs = github::get::<WorkflowRuns, _>(
+        "workflow_runs",
+        format!(
+            "repos/{owner}/{repo}/actions/workflows/{workflow_id}/runs",
+            owner = repo.owner,
+            repo = repo.name,
+            workflow_id = workflow.id,
+        ),
+        Some(&[("per_page", "1")]),
+    )
+    .await?;
+
+    let run = match runs.workflow_runs.first() {
+        Some(run) => run,
+        None => return Ok(()),
+    };
+    let labels = [
+        repo.owner.as_str(),
+        repo.name.as_str(),
+        workflow.name.as_str(),
+    ];
+
+    ACTIONS_WORKFLOW_LAST_RUN_TIMESTAMP
+        .with_label_values(&labels)
+        .set(run.created_at.timestamp() as f64);
+    expiry::touch(&*ACTIONS_WORKFLOW_LAST_RUN_TIMESTAMP, &labels);
+
+    // Runs still in progress keep the status of the previous one.
+    if let Some(conclusion) = &run.conclusion {
+        ACTIONS_WORKFLOW_LAST_RUN_STATUS
+            .with_label_values(&labels)
+            .set((conclusion == "success") as i64);
+        expiry::touch(&*ACTIONS_WORKFLOW_LAST_RUN_STATUS, &labels);
+    }
+
+    info!("polled last run for {}:{}", repo, workflow.name);
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRuns {
+    pub workflow_runs: Vec<WorkflowRun>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRun {
+    pub conclusion: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+lazy_static! {
+    pub static ref ACTIONS_WORKFLOW_LAST_RUN_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "github_actions_workflow_last_run_timestamp_seconds",
+        "Unix time at which the most recent run of the Github Actions workflow was created",
+        &["owner", "repository", "workflow"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_WORKFLOW_LAST_RUN_STATUS: IntGaugeVec = register_int_gauge_vec!(
+        "github_actions_workflow_last_run_status",
+        "Whether the most recent completed run of the Github Actions workflow succeeded (1) or not (0)",
+        &["owner", "repository", "workflow"]
+    )
+    .unwrap();
+}
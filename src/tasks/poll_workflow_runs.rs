@@ -0,0 +1,185 @@
+use super::runs::get_completed_runs;
+use crate::{
+    metrics, pause,
+    repositories::Repositories,
+    rotation::Rotation,
+    shutdown::Shutdown,
+    types::{Repository, Workflow},
+    watchdog,
+};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use lazy_static::lazy_static;
+use octocrab::models::WorkflowId;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+/// Conclusions exported as is. No-op runs, e.g. skipped by `paths` or
+/// `branches` filters, keep their own conclusion so that they can be told
+/// apart from runs that did work.
+static CONCLUSIONS: &[&str] = &[
+    "success",
+    "failure",
+    "cancelled",
+    "timed_out",
+    "skipped",
+    "neutral",
+    "action_required",
+    "stale",
+    "startup_failure",
+];
+
+pub async fn poll_workflow_runs(
+    github_workflows: Repositories,
+    duration_buckets: Vec<f64>,
+    mut rotation: Rotation,
+    sleep: Duration,
+    mut shutdown: Shutdown,
+) {
+    // Buckets are configurable, so the histogram can't be a static.
+    let durations = register_histogram_vec!(
+        "github_actions_run_duration_seconds",
+        "Wall-clock time between a Github Actions workflow run being created and completing",
+        &["owner", "repository", "workflow"],
+        duration_buckets
+    )
+    .unwrap();
+    let mut counted = HashMap::new();
+
+    loop {
+        watchdog::beat("workflow_runs", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            for repo in &rotation.select(github_workflows.list().await) {
+                let workflows = github_workflows.workflows(repo).await;
+
+                if let Err(err) =
+                    poll_workflow_runs_for_repo(repo, &workflows, &durations, &mut counted).await
+                {
+                    metrics::poll_failed("workflow_runs", &err);
+                    error!("failed to poll workflow runs for repo {}: {}", repo, err);
+                } else {
+                    metrics::poll_succeeded("workflow_runs");
+                    info!("polled workflow runs for {}", repo);
+                }
+            }
+
+            metrics::poll_cycle_completed("workflow_runs", started);
+        }
+
+        if shutdown.sleep(sleep).await {
+            break;
+        }
+    }
+}
+
+/// When counting started for a repository, and the runs counted since.
+struct CountedRuns {
+    since: DateTime<Utc>,
+    ids: HashSet<u64>,
+}
+
+/// Counts runs that completed since the previous poll, and observes their
+/// durations. The first poll of a repository only records when counting
+/// starts, so that historical runs aren't counted.
+///
+/// Only runs created within the last day are looked at, runs taking longer
+/// than that to complete are missed.
+async fn poll_workflow_runs_for_repo(
+    repo: &Repository,
+    workflows: &[Workflow],
+    durations: &HistogramVec,
+    counted: &mut HashMap<Repository, CountedRuns>,
+) -> anyhow::Result<()> {
+    let now = Utc::now();
+
+    let counted = match counted.get_mut(repo) {
+        Some(counted) => counted,
+        None => {
+            counted.insert(
+                repo.clone(),
+                CountedRuns {
+                    since: now,
+                    ids: HashSet::new(),
+                },
+            );
+            return Ok(());
+        }
+    };
+
+    let names = workflows
+        .iter()
+        .map(|w| (w.id, w.name.as_str()))
+        .collect::<HashMap<WorkflowId, _>>();
+    let runs = get_completed_runs::<WorkflowRun>(repo, now - ChronoDuration::days(1)).await?;
+
+    for run in &runs {
+        if run.updated_at < counted.since || counted.ids.contains(&run.id) {
+            continue;
+        }
+
+        // Runs of workflows not cached yet can't be named, they are counted
+        // on a later poll once the workflow is.
+        let workflow = match names.get(&run.workflow_id) {
+            Some(workflow) => workflow,
+            None => continue,
+        };
+        counted.ids.insert(run.id);
+
+        ACTIONS_WORKFLOW_RUNS
+            .with_label_values(&[
+                &repo.owner,
+                &repo.name,
+                workflow,
+                conclusion_label(run.conclusion.as_deref()),
+            ])
+            .inc();
+
+        let duration = (run.updated_at - run.created_at).num_seconds().max(0);
+        durations
+            .with_label_values(&[&repo.owner, &repo.name, workflow])
+            .observe(duration as f64);
+    }
+
+    // Runs that fell out of the window won't be returned again.
+    let current = runs.iter().map(|r| r.id).collect::<HashSet<_>>();
+    counted.ids.retain(|id| current.contains(id));
+
+    Ok(())
+}
+
+/// Unexpected conclusions are exported as `other` rather than one series each.
+fn conclusion_label(conclusion: Option<&str>) -> &'static str {
+    match conclusion {
+        Some(conclusion) => CONCLUSIONS
+            .iter()
+            .find(|c| **c == conclusion)
+            .copied()
+            .unwrap_or("other"),
+        None => "unknown",
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRun {
+    pub id: u64,
+    pub workflow_id: WorkflowId,
+    pub conclusion: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+lazy_static! {
+    pub static ref ACTIONS_WORKFLOW_RUNS: IntCounterVec = register_int_counter_vec!(
+        "github_actions_workflow_runs_total",
+        "Number of completed Github Actions workflow runs by conclusion",
+        &["owner", "repository", "workflow", "conclusion"]
+    )
+    .unwrap();
+}
@@ -1,50 +1,203 @@
-use crate::types::{Repository, Workflow};
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::{sync::RwLock, time};
-use tracing::{error, info};
+use crate::{
+    expiry, github, metrics, pause,
+    repositories::Repositories,
+    shutdown::Shutdown,
+    types::{NamePattern, Repository, Workflow},
+    watchdog,
+};
+use futures::stream::{self, StreamExt};
+use lazy_static::lazy_static;
+use octocrab::{models::workflows::WorkFlow, Page};
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+const PER_PAGE: usize = 100;
+/// Guards against following `next` links forever.
+const MAX_PAGES: usize = 10;
+
+#[derive(Debug, Clone, Default)]
+pub struct WorkflowsOptions {
+    /// Maximum number of workflows kept for each repository.
+    pub max_workflows: Option<usize>,
+    /// When non-empty, only workflows whose name matches one of these are kept.
+    pub include: Vec<NamePattern>,
+    /// Workflows whose name matches one of these are dropped, even if included.
+    pub exclude: Vec<NamePattern>,
+    /// Number of repositories polled at the same time.
+    pub concurrency: usize,
+}
 
 pub async fn poll_workflows(
-    github_workflows: Arc<HashMap<Repository, RwLock<Vec<Workflow>>>>,
+    github_workflows: Repositories,
+    options: WorkflowsOptions,
     sleep: Duration,
+    mut shutdown: Shutdown,
 ) {
     loop {
-        for (repo, workflows) in github_workflows.iter() {
-            if let Err(err) = poll_workflow(repo, workflows).await {
-                error!("failed to fetch workflows for repo {}: {}", repo, err);
-            }
+        watchdog::beat("workflows", sleep);
+
+        if !pause::is_paused() {
+            let started = Instant::now();
+
+            stream::iter(&github_workflows.list().await)
+                .for_each_concurrent(options.concurrency, |repo| {
+                    let github_workflows = &github_workflows;
+                    let options = &options;
+
+                    async move {
+                        match poll_workflow(repo, github_workflows, options).await {
+                            Ok(()) => metrics::poll_succeeded("workflows"),
+                            Err(err) => {
+                                metrics::poll_failed("workflows", &err);
+                                error!("failed to fetch workflows for repo {}: {}", repo, err);
+                            }
+                        }
+                    }
+                })
+                .await;
+
+            metrics::poll_cycle_completed("workflows", started);
         }
 
-        time::sleep(sleep).await;
+        if shutdown.sleep(sleep).await {
+            break;
+        }
     }
 }
 
-async fn poll_workflow(repo: &Repository, workflows: &RwLock<Vec<Workflow>>) -> anyhow::Result<()> {
-    let octocrab = octocrab::instance();
+async fn poll_workflow(
+    repo: &Repository,
+    github_workflows: &Repositories,
+    options: &WorkflowsOptions,
+) -> anyhow::Result<()> {
+    let mut page = github::get::<Page<WorkFlow>, _>(
+        "workflows",
+        format!(
+            "repos/{owner}/{repo}/actions/workflows",
+            owner = repo.owner,
+            repo = repo.name,
+        ),
+        Some(&[("per_page", PER_PAGE.to_string())]),
+    )
+    .await?;
 
-    let page = octocrab
-        .workflows(&repo.owner, &repo.name)
-        .list()
-        .per_page(100)
-        .send()
-        .await?;
+    let mut workflows = Vec::new();
+    let mut pages = 1;
+
+    loop {
+        workflows.append(&mut page.items);
+
+        if page.next.is_some() && pages == MAX_PAGES {
+            warn!(
+                "repo {} has more than {} workflows, ignoring the rest",
+                repo,
+                MAX_PAGES * PER_PAGE
+            );
+            break;
+        }
+
+        match github::get_next_page("workflows", &repo.owner, &page).await? {
+            Some(next) => page = next,
+            None => break,
+        }
+
+        pages += 1;
+    }
 
-    let updated_workflows = page
+    let mut found_workflows = workflows
+        .into_iter()
+        .filter(|w| {
+            options.include.is_empty() || options.include.iter().any(|p| p.matches(&w.name))
+        })
+        .filter(|w| !options.exclude.iter().any(|p| p.matches(&w.name)))
+        .collect::<Vec<_>>();
+
+    if let Some(max) = options.max_workflows {
+        // Prefer active workflows, then the most recently updated ones.
+        found_workflows.sort_by_key(|w| (w.state != "active", Reverse(w.updated_at)));
+        found_workflows.truncate(max);
+    }
+
+    let updated_workflows = found_workflows
         .into_iter()
         .map(|w| Workflow {
             id: w.id,
             name: w.name,
+            path: w.path,
+            state: w.state,
         })
         .collect::<Vec<_>>();
 
+    for workflow in &updated_workflows {
+        ACTIONS_WORKFLOW_STATE
+            .with_label_values(&[&repo.owner, &repo.name, &workflow.name])
+            .set((workflow.state == "active") as i64);
+        expiry::touch(
+            &*ACTIONS_WORKFLOW_STATE,
+            &[&repo.owner, &repo.name, &workflow.name],
+        );
+    }
+
     info!(
         "found workflows for repo `{}`: {:?}",
         repo, updated_workflows
     );
 
-    {
-        let mut w = workflows.write().await;
-        *w = updated_workflows;
+    ACTIONS_WORKFLOWS
+        .with_label_values(&[&repo.owner, &repo.name])
+        .set(updated_workflows.len() as i64);
+    expiry::touch(&*ACTIONS_WORKFLOWS, &[&repo.owner, &repo.name]);
+
+    let none_found = updated_workflows.is_empty();
+    let names = updated_workflows
+        .iter()
+        .map(|w| w.name.clone())
+        .collect::<HashSet<_>>();
+    let previous = github_workflows
+        .set_workflows(repo, updated_workflows)
+        .await
+        .unwrap_or_default();
+
+    for workflow in previous.iter().filter(|w| !names.contains(&w.name)) {
+        let _ =
+            ACTIONS_WORKFLOW_STATE.remove_label_values(&[&repo.owner, &repo.name, &workflow.name]);
+    }
+
+    // Workflows rarely all go away at once, this usually means the token
+    // lost access to the repository.
+    if !previous.is_empty() && none_found {
+        warn!("all workflows vanished from repo {}", repo);
+
+        ACTIONS_WORKFLOWS_VANISHED
+            .with_label_values(&[&repo.owner, &repo.name])
+            .inc();
     }
 
     Ok(())
 }
+
+lazy_static! {
+    pub static ref ACTIONS_WORKFLOWS: IntGaugeVec = register_int_gauge_vec!(
+        "github_actions_workflows_total",
+        "Number of Github Actions workflows polled in the repository",
+        &["owner", "repository"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_WORKFLOW_STATE: IntGaugeVec = register_int_gauge_vec!(
+        "github_actions_workflow_state",
+        "Whether a Github Actions workflow is active (1) or e.g. disabled (0)",
+        &["owner", "repository", "workflow"]
+    )
+    .unwrap();
+    pub static ref ACTIONS_WORKFLOWS_VANISHED: IntCounterVec = register_int_counter_vec!(
+        "github_actions_workflows_vanished_total",
+        "Number of times all workflows of a Github repository disappeared at once",
+        &["owner", "repository"]
+    )
+    .unwrap();
+}
@@ -0,0 +1,76 @@
+use crate::{github, types::Repository};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de::DeserializeOwned, Deserialize};
+use tracing::warn;
+
+const PER_PAGE: usize = 100;
+/// Guards against listing the runs of a very busy repository forever.
+const MAX_PAGES: usize = 10;
+
+/// Completed runs of the repository created after `created_after`, up to
+/// `MAX_PAGES` pages of the most recent ones.
+pub async fn get_completed_runs<R: DeserializeOwned>(
+    repo: &Repository,
+    created_after: DateTime<Utc>,
+) -> anyhow::Result<Vec<R>> {
+    let created = format!(
+        ">={}",
+        created_after.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+
+    get_runs(
+        repo,
+        &[("status", "completed"), ("created", created.as_str())],
+    )
+    .await
+}
+
+/// Runs of the repository matching `filters`, e.g. a `status`, up to
+/// `MAX_PAGES` pages of the most recent ones.
+pub async fn get_runs<R: DeserializeOwned>(
+    repo: &Repository,
+    filters: &[(&str, &str)],
+) -> anyhow::Result<Vec<R>> {
+    let mut runs = Vec::new();
+
+    for page in 1..=MAX_PAGES {
+        let page = page.to_string();
+        let per_page = PER_PAGE.to_string();
+
+        let mut params = filters.to_vec();
+        params.push(("per_page", per_page.as_str()));
+        params.push(("page", page.as_str()));
+
+        let response = github::get::<WorkflowRuns<R>, _>(
+            "repo_runs",
+            format!(
+                "repos/{owner}/{repo}/actions/runs",
+                owner = repo.owner,
+                repo = repo.name,
+            ),
+            Some(&params),
+        )
+        .await?;
+
+        let last_page = response.workflow_runs.len() < PER_PAGE;
+        runs.extend(response.workflow_runs);
+
+        if last_page {
+            return Ok(runs);
+        }
+    }
+
+    warn!(
+        "repo {} has more than {} runs matching {:?}, ignoring the rest",
+        repo,
+        MAX_PAGES * PER_PAGE,
+        filters
+    );
+
+    Ok(runs)
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkflowRuns<R> {
+    workflow_runs: Vec<R>,
+}
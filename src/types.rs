@@ -1,9 +1,15 @@
+use crate::metrics;
 use std::{
     fmt::{Debug, Display},
     str::FromStr,
+    sync::Arc,
 };
 
+use arc_swap::ArcSwap;
+
+use hyper::header::{HeaderName, HeaderValue};
 use octocrab::models::WorkflowId;
+use regex::Regex;
 
 pub static UBUNTU: &str = "ubuntu";
 pub static MACOS: &str = "macos";
@@ -11,7 +17,10 @@ pub static WINDOWS: &str = "windows";
 
 pub type Organisation = String;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+/// Organisations shared between tasks, swapped when the config file changes.
+pub type Organisations = Arc<ArcSwap<Vec<Organisation>>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Repository {
     pub owner: Organisation,
     pub name: String,
@@ -38,10 +47,170 @@ impl Display for Repository {
     }
 }
 
-#[derive(Debug)]
+/// Extra header attached to every GitHub API request.
+#[derive(Debug, Clone)]
+pub struct ApiHeader {
+    pub name: HeaderName,
+    pub value: String,
+}
+
+impl FromStr for ApiHeader {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or("header must be in format {name}={value}!")?;
+
+        let name = HeaderName::from_str(name.trim()).map_err(|_| "invalid header name!")?;
+        HeaderValue::from_str(value).map_err(|_| "invalid header value!")?;
+
+        Ok(ApiHeader {
+            name,
+            value: value.into(),
+        })
+    }
+}
+
+/// Token used for requests about one owner instead of the main token.
+#[derive(Debug, Clone)]
+pub struct OwnerToken {
+    pub owner: Organisation,
+    pub token: String,
+}
+
+impl FromStr for OwnerToken {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (owner, token) = s
+            .split_once('=')
+            .ok_or("token mapping must be in format {owner}={token}!")?;
+
+        let owner = owner.trim();
+        let token = token.trim();
+
+        if owner.is_empty() || token.is_empty() {
+            return Err("owner and token must not be empty!");
+        }
+
+        Ok(OwnerToken {
+            owner: owner.into(),
+            token: token.into(),
+        })
+    }
+}
+
+/// Label with a fixed value added to every exported series.
+#[derive(Debug, Clone)]
+pub struct StaticLabel {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for StaticLabel {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or("static label must be in format {name}={value}!")?;
+
+        let name = name.trim();
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !name.starts_with("__");
+
+        if !valid {
+            return Err("invalid label name!");
+        }
+
+        if metrics::RESERVED_LABELS.contains(&name) {
+            return Err("label name is reserved!");
+        }
+
+        Ok(StaticLabel {
+            name: name.into(),
+            value: value.into(),
+        })
+    }
+}
+
+/// Metrics polled on top of the workflow and billing ones, each costing
+/// extra API requests and thus only when enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Collector {
+    CacheUsage,
+    Collaborators,
+    Contributors,
+    Deployments,
+    Orgs,
+    QueuedRuns,
+    Repos,
+    Runners,
+    ScheduleDrift,
+    SelfhostedJobs,
+    WorkflowLastRun,
+    WorkflowRuns,
+}
+
+impl FromStr for Collector {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cache_usage" => Ok(Collector::CacheUsage),
+            "collaborators" => Ok(Collector::Collaborators),
+            "contributors" => Ok(Collector::Contributors),
+            "deployments" => Ok(Collector::Deployments),
+            "orgs" => Ok(Collector::Orgs),
+            "queued_runs" => Ok(Collector::QueuedRuns),
+            "repos" => Ok(Collector::Repos),
+            "runners" => Ok(Collector::Runners),
+            "schedule_drift" => Ok(Collector::ScheduleDrift),
+            "selfhosted_jobs" => Ok(Collector::SelfhostedJobs),
+            "workflow_last_run" => Ok(Collector::WorkflowLastRun),
+            "workflow_runs" => Ok(Collector::WorkflowRuns),
+            _ => Err("unknown collector!"),
+        }
+    }
+}
+
+/// Unit in which workflow billable time is exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BillableUnit {
+    Milliseconds,
+    Seconds,
+}
+
+impl BillableUnit {
+    pub fn convert(self, ms: f64) -> f64 {
+        match self {
+            BillableUnit::Milliseconds => ms,
+            BillableUnit::Seconds => ms / 1000.0,
+        }
+    }
+}
+
+impl FromStr for BillableUnit {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "milliseconds" => Ok(BillableUnit::Milliseconds),
+            "seconds" => Ok(BillableUnit::Seconds),
+            _ => Err("billable unit must be either `milliseconds` or `seconds`!"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Workflow {
     pub id: WorkflowId,
     pub name: String,
+    pub path: String,
+    /// `active`, or why the workflow doesn't run, e.g. `disabled_manually`.
+    pub state: String,
 }
 
 impl Display for Workflow {
@@ -49,3 +218,139 @@ impl Display for Workflow {
         write!(f, "({}) {}", self.id, self.name)
     }
 }
+
+/// Selects workflows by `{owner}/{repo}:{workflow}`, or just `{workflow}` for
+/// every repository. Each part may contain `*` wildcards.
+#[derive(Debug, Clone)]
+pub struct WorkflowPattern {
+    repo: String,
+    workflow: String,
+}
+
+impl WorkflowPattern {
+    pub fn matches(&self, repo: &Repository, workflow: &Workflow) -> bool {
+        glob_matches(&self.repo, &repo.to_string()) && glob_matches(&self.workflow, &workflow.name)
+    }
+}
+
+impl FromStr for WorkflowPattern {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Workflow names may contain colons too, only a leading `{owner}/{repo}`
+        // makes the pattern repository specific.
+        let (repo, workflow) = match s.split_once(':') {
+            Some((repo, workflow)) if repo.contains('/') => (repo, workflow),
+            _ => ("*", s),
+        };
+
+        if workflow.is_empty() {
+            return Err("workflow pattern must name a workflow!");
+        }
+
+        Ok(WorkflowPattern {
+            repo: repo.into(),
+            workflow: workflow.into(),
+        })
+    }
+}
+
+/// Last-mile change to the exposed series.
+#[derive(Debug, Clone)]
+pub enum RelabelRule {
+    /// `drop:{label}={regex}` drops series whose label value fully matches,
+    /// a missing label having an empty value.
+    Drop { label: String, regex: Regex },
+    /// `rename:{from}={to}` renames a label.
+    Rename { from: String, to: String },
+}
+
+impl FromStr for RelabelRule {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = "relabel rule must be in format drop:{label}={regex} or rename:{from}={to}!";
+
+        let (action, rule) = s.split_once(':').ok_or(invalid)?;
+        let (left, right) = rule.split_once('=').ok_or(invalid)?;
+
+        if left.is_empty() {
+            return Err(invalid);
+        }
+
+        match action {
+            "drop" => Ok(RelabelRule::Drop {
+                label: left.into(),
+                regex: Regex::new(&format!("^(?:{})$", right))
+                    .map_err(|_| "invalid relabel regex!")?,
+            }),
+            "rename" if !right.is_empty() => Ok(RelabelRule::Rename {
+                from: left.into(),
+                to: right.into(),
+            }),
+            _ => Err(invalid),
+        }
+    }
+}
+
+/// Case-insensitive pattern on names, e.g. of workflows or of repositories as
+/// `owner/repo`, where `*` matches anything.
+#[derive(Debug, Clone)]
+pub struct NamePattern(String);
+
+impl NamePattern {
+    pub fn matches(&self, name: &str) -> bool {
+        glob_matches(&self.0, &name.to_lowercase())
+    }
+}
+
+impl FromStr for NamePattern {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("name pattern must not be empty!");
+        }
+
+        Ok(NamePattern(s.to_lowercase()))
+    }
+}
+
+/// Whether `s` matches `pattern`, where `*` stands for any run of characters.
+fn glob_matches(pattern: &str, s: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == s,
+        Some((prefix, rest)) => match s.strip_prefix(prefix) {
+            Some(s) => s
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(Some(s.len()))
+                .any(|i| glob_matches(rest, &s[i..])),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_static_labels() {
+        let label = "environment=prod".parse::<StaticLabel>().unwrap();
+        assert_eq!(label.name, "environment");
+        assert_eq!(label.value, "prod");
+
+        assert!("job=exporter".parse::<StaticLabel>().is_ok());
+        assert!(" team =ci".parse::<StaticLabel>().is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_static_labels() {
+        assert!("team".parse::<StaticLabel>().is_err());
+        assert!("1team=ci".parse::<StaticLabel>().is_err());
+        assert!("__name__=x".parse::<StaticLabel>().is_err());
+        assert!("le=x".parse::<StaticLabel>().is_err());
+        assert!(" quantile =x".parse::<StaticLabel>().is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{github, pause};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time;
+use tracing::error;
+
+/// How many poll intervals a task may go without progressing.
+const STALL_MULTIPLIER: u32 = 3;
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Records that `task`, which polls every `interval`, started an iteration.
+pub fn beat(task: &'static str, interval: Duration) {
+    HEARTBEATS
+        .lock()
+        .unwrap()
+        .insert(task, (Instant::now(), interval));
+}
+
+/// Records that `task` progressed within an iteration, e.g. polled one
+/// repository, so that a long but healthy iteration isn't taken for a stall.
+pub fn progress(task: &str) {
+    if let Some((last_beat, _)) = HEARTBEATS.lock().unwrap().get_mut(task) {
+        *last_beat = Instant::now();
+    }
+}
+
+/// Exits the process once any task stops progressing, so that the
+/// orchestrator restarts the exporter instead of serving stale data.
+pub async fn watch() {
+    loop {
+        time::sleep(CHECK_INTERVAL).await;
+
+        let mut heartbeats = HEARTBEATS.lock().unwrap();
+
+        // Tasks waiting on a pause or on GitHub's secondary rate limit aren't
+        // stalled, their time starts over once the wait is over.
+        if pause::is_paused() || github::is_throttled() {
+            for (last_beat, _) in heartbeats.values_mut() {
+                *last_beat = Instant::now();
+            }
+
+            continue;
+        }
+
+        for (task, (last_beat, interval)) in heartbeats.iter() {
+            let elapsed = last_beat.elapsed();
+
+            if elapsed > *interval * STALL_MULTIPLIER {
+                error!(
+                    "task `{}` has not progressed for {:?}, exiting",
+                    task, elapsed
+                );
+
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref HEARTBEATS: Mutex<HashMap<&'static str, (Instant, Duration)>> =
+        Mutex::new(HashMap::new());
+}